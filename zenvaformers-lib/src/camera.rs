@@ -0,0 +1,239 @@
+use gdnative::{
+    api::{Camera2D, GlobalConstants, InputEventMouseButton, InputEventMouseMotion},
+    prelude::*,
+};
+
+use crate::Map;
+
+// Screen pixels ensure_visible keeps between a position and the edge of the view.
+const VISIBLE_MARGIN: f32 = 64.0;
+
+// Pans with the keyboard, the screen edges or a middle mouse drag, zooms with the wheel,
+// and keeps the view over the map.
+#[derive(NativeClass)]
+#[inherit(Camera2D)]
+pub struct CameraController {
+    // Pixels per second at zoom 1.
+    #[property(default = 600.0)]
+    pan_speed: f32,
+    #[property(default = true)]
+    edge_scroll: bool,
+    // How close to the window edge the mouse has to be to scroll.
+    #[property(default = 16.0)]
+    edge_margin: f32,
+    // Camera2D zoom: below 1 is closer, above 1 is further away.
+    #[property(default = 0.5)]
+    min_zoom: f32,
+    #[property(default = 2.5)]
+    max_zoom: f32,
+    #[property(default = 1.1)]
+    zoom_step: f32,
+    // Screen height covered by the UI panel, so the bottom row can scroll clear of it.
+    #[property(default = 144.0)]
+    hud_height: f32,
+    dragging: bool,
+}
+
+#[methods]
+impl CameraController {
+    fn new(_base: &Camera2D) -> Self {
+        CameraController {
+            pan_speed: 600.0,
+            edge_scroll: true,
+            edge_margin: 16.0,
+            min_zoom: 0.5,
+            max_zoom: 2.5,
+            zoom_step: 1.1,
+            hud_height: 144.0,
+            dragging: false,
+        }
+    }
+
+    fn get_map(base: &Camera2D) -> TInstance<'static, Map> {
+        unsafe {
+            base.get_node_as_instance::<Map>("/root/MainScene/Tiles")
+                .unwrap()
+        }
+    }
+
+    #[method]
+    fn _ready(&self, #[base] base: &Camera2D) {
+        godot_print!("Hello from Camera!");
+        base.make_current();
+        let start = Self::get_map(base)
+            .map(|m, map_base| {
+                m.grid()
+                    .start_tile()
+                    .map(|coord| m.grid_to_world(&map_base, coord))
+            })
+            .unwrap();
+        if let Some(start) = start {
+            self.focus_on(base, start);
+        }
+    }
+
+    // Centres the view on a world position, as far as the map bounds allow.
+    #[method]
+    fn focus_on(&self, #[base] base: &Camera2D, position: Vector2) {
+        base.set_position(position);
+        self.clamp_to_map(base);
+    }
+
+    // Scrolls just far enough to bring a world position into view, clear of the HUD.
+    #[method]
+    fn ensure_visible(&self, #[base] base: &Camera2D, position: Vector2) {
+        let viewport_size = match base.get_viewport() {
+            Some(viewport) => unsafe { viewport.assume_safe() }.get_visible_rect().size,
+            None => return,
+        };
+        let zoom = base.zoom().x;
+        let margin = VISIBLE_MARGIN * zoom;
+        let half = viewport_size * zoom / 2.0;
+        let centre = base.get_camera_screen_center();
+        let min = centre - half + Vector2::new(margin, margin);
+        let max = centre + half - Vector2::new(margin, margin + self.hud_height * zoom);
+
+        let shift_axis = |value: f32, min: f32, max: f32| {
+            if value < min {
+                value - min
+            } else if value > max {
+                value - max
+            } else {
+                0.0
+            }
+        };
+        let shift = Vector2::new(
+            shift_axis(position.x, min.x, max.x),
+            shift_axis(position.y, min.y, max.y),
+        );
+        if shift != Vector2::ZERO {
+            base.set_position(base.position() + shift);
+            self.clamp_to_map(base);
+        }
+    }
+
+    #[method]
+    fn _process(&self, #[base] base: &Camera2D, delta: f64) {
+        let input = Input::godot_singleton();
+        let mut direction = Vector2::new(
+            input.get_action_strength("camera_right", false)
+                - input.get_action_strength("camera_left", false),
+            input.get_action_strength("camera_down", false)
+                - input.get_action_strength("camera_up", false),
+        );
+
+        if self.edge_scroll && !self.dragging {
+            direction += self.edge_direction(base);
+        }
+        if direction == Vector2::ZERO {
+            return;
+        }
+
+        let zoom = base.zoom().x;
+        let step = direction.normalized() * self.pan_speed * zoom * delta as f32;
+        base.set_position(base.position() + step);
+        self.clamp_to_map(base);
+    }
+
+    fn edge_direction(&self, base: &Camera2D) -> Vector2 {
+        let viewport = match base.get_viewport() {
+            Some(viewport) => unsafe { viewport.assume_safe() },
+            None => return Vector2::ZERO,
+        };
+        let size = viewport.get_visible_rect().size;
+        let mouse = viewport.get_mouse_position();
+        // The mouse sitting outside the window is not a request to scroll.
+        if mouse.x < 0.0 || mouse.y < 0.0 || mouse.x > size.x || mouse.y > size.y {
+            return Vector2::ZERO;
+        }
+
+        let mut direction = Vector2::ZERO;
+        if mouse.x < self.edge_margin {
+            direction.x -= 1.0;
+        } else if mouse.x > size.x - self.edge_margin {
+            direction.x += 1.0;
+        }
+        if mouse.y < self.edge_margin {
+            direction.y -= 1.0;
+        } else if mouse.y > size.y - self.edge_margin {
+            direction.y += 1.0;
+        }
+        direction
+    }
+
+    // Only sees input the UI didn't take, so clicks on buttons never pan or zoom.
+    #[method]
+    fn _unhandled_input(&mut self, #[base] base: &Camera2D, event: Ref<InputEvent>) {
+        let event = unsafe { event.assume_safe() };
+        if let Some(button) = event.cast::<InputEventMouseButton>() {
+            match button.button_index() {
+                GlobalConstants::BUTTON_WHEEL_UP if button.is_pressed() => {
+                    self.zoom_at(base, 1.0 / self.zoom_step, button.position())
+                }
+                GlobalConstants::BUTTON_WHEEL_DOWN if button.is_pressed() => {
+                    self.zoom_at(base, self.zoom_step, button.position())
+                }
+                GlobalConstants::BUTTON_MIDDLE => self.dragging = button.is_pressed(),
+                _ => {}
+            }
+        } else if let Some(motion) = event.cast::<InputEventMouseMotion>() {
+            if self.dragging {
+                let zoom = base.zoom();
+                let relative = motion.relative();
+                base.set_position(
+                    base.position() - Vector2::new(relative.x * zoom.x, relative.y * zoom.y),
+                );
+                self.clamp_to_map(base);
+            }
+        }
+    }
+
+    // Zooms by `factor`, keeping the world point under the mouse where it is on screen.
+    fn zoom_at(&self, base: &Camera2D, factor: f32, screen_position: Vector2) {
+        let old_zoom = base.zoom().x;
+        let new_zoom = (old_zoom * factor).clamp(self.min_zoom, self.max_zoom);
+        if new_zoom == old_zoom {
+            return;
+        }
+
+        let viewport_size = match base.get_viewport() {
+            Some(viewport) => unsafe { viewport.assume_safe() }.get_visible_rect().size,
+            None => return,
+        };
+        let from_centre = screen_position - viewport_size / 2.0;
+        let under_mouse = base.position() + from_centre * old_zoom;
+        base.set_zoom(Vector2::new(new_zoom, new_zoom));
+        base.set_position(under_mouse - from_centre * new_zoom);
+        self.clamp_to_map(base);
+    }
+
+    // Keeps the view inside the map, or centred on it when the map is smaller than the view.
+    fn clamp_to_map(&self, base: &Camera2D) {
+        let viewport_size = match base.get_viewport() {
+            Some(viewport) => unsafe { viewport.assume_safe() }.get_visible_rect().size,
+            None => return,
+        };
+        let bounds = Self::get_map(base)
+            .map(|m, map_base| m.world_bounds(&map_base))
+            .unwrap();
+
+        let zoom = base.zoom().x;
+        let view = viewport_size * zoom;
+        let hud = self.hud_height * zoom;
+        // Let the view run down past the map by the height of the HUD.
+        let (min, max) = (bounds.position, bounds.position + bounds.size);
+        let clamp_axis = |value: f32, min: f32, max: f32, view: f32| {
+            if max - min <= view {
+                (min + max) / 2.0
+            } else {
+                value.clamp(min + view / 2.0, max - view / 2.0)
+            }
+        };
+
+        let position = base.position();
+        base.set_position(Vector2::new(
+            clamp_axis(position.x, min.x, max.x, view.x),
+            clamp_axis(position.y, min.y, max.y + hud, view.y),
+        ));
+    }
+}
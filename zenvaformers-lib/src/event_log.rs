@@ -0,0 +1,80 @@
+use gdnative::prelude::*;
+
+use crate::i18n::tr_args;
+
+// How many toasts can be up at once. Older ones make way for new ones.
+pub const MAX_TOASTS: usize = 4;
+pub const TOAST_SECONDS: f64 = 4.0;
+// Buildings on the map, not counting the Base, that are worth a milestone.
+pub const BUILDING_MILESTONES: [usize; 4] = [5, 10, 25, 50];
+// Every this many turns survived is a milestone.
+pub const TURN_MILESTONE: i32 = 10;
+
+#[derive(Clone, PartialEq)]
+pub enum LogKind {
+    Building = 0,
+    Event = 1,
+    Shortage = 2,
+    Milestone = 3,
+}
+
+impl LogKind {
+    // Important entries are also shown as toasts.
+    pub fn is_important(&self) -> bool {
+        matches!(self, LogKind::Shortage | LogKind::Milestone)
+    }
+
+    pub fn get_color(&self) -> Color {
+        match self {
+            LogKind::Building => Color::from_rgb(0.9, 0.9, 0.9),
+            LogKind::Event => Color::from_rgb(0.55, 0.85, 1.0),
+            LogKind::Shortage => Color::from_rgb(0.95, 0.4, 0.35),
+            LogKind::Milestone => Color::from_rgb(1.0, 0.85, 0.2),
+        }
+    }
+}
+
+impl ToVariant for LogKind {
+    fn to_variant(&self) -> Variant {
+        match self {
+            LogKind::Building => 0.to_variant(),
+            LogKind::Event => 1.to_variant(),
+            LogKind::Shortage => 2.to_variant(),
+            LogKind::Milestone => 3.to_variant(),
+        }
+    }
+}
+
+impl FromVariant for LogKind {
+    fn from_variant(variant: &Variant) -> Result<Self, FromVariantError> {
+        let result = i64::from_variant(variant)?;
+        match result {
+            0 => Ok(LogKind::Building),
+            1 => Ok(LogKind::Event),
+            2 => Ok(LogKind::Shortage),
+            3 => Ok(LogKind::Milestone),
+            _ => Err(FromVariantError::UnknownEnumVariant {
+                variant: "i64".to_owned(),
+                expected: &["0", "1", "2", "3"],
+            }),
+        }
+    }
+}
+
+// One line of the colony's event log.
+#[derive(Clone, ToVariant, FromVariant)]
+pub struct LogEntry {
+    pub turn: i32,
+    pub kind: LogKind,
+    pub text: String,
+}
+
+impl LogEntry {
+    // text is kept in the language it was logged in.
+    pub fn line(&self) -> String {
+        tr_args(
+            "Turn {turn}: {text}",
+            &[("turn", &self.turn), ("text", &self.text)],
+        )
+    }
+}
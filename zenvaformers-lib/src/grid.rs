@@ -0,0 +1,195 @@
+use gdnative::{
+    export::{
+        hint::{EnumHint, IntHint},
+        Export,
+    },
+    prelude::*,
+};
+
+// Integer position of a tile on the map. (0, 0) is the top left tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ToVariant, FromVariant)]
+pub struct GridCoord {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl GridCoord {
+    pub fn new(x: i32, y: i32) -> Self {
+        GridCoord { x, y }
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32) -> Self {
+        GridCoord::new(self.x + dx, self.y + dy)
+    }
+}
+
+// How tiles are arranged on screen. Coordinates stay (column, row) in every layout so a
+// width * height map is always a plain rectangle of data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GridLayout {
+    Square = 0,
+    // Pointy topped hexes, odd rows shoved half a tile right.
+    PointyHex = 1,
+    // Flat topped hexes, odd columns shoved half a tile down.
+    FlatHex = 2,
+    // Diamond shaped tiles, columns run down-right and rows run down-left.
+    Isometric = 3,
+}
+
+const SQUARE_NEIGHBOURS: [(i32, i32); 4] = [(0, -1), (0, 1), (1, 0), (-1, 0)];
+const POINTY_HEX_EVEN_NEIGHBOURS: [(i32, i32); 6] =
+    [(1, 0), (-1, 0), (0, -1), (-1, -1), (0, 1), (-1, 1)];
+const POINTY_HEX_ODD_NEIGHBOURS: [(i32, i32); 6] =
+    [(1, 0), (-1, 0), (1, -1), (0, -1), (1, 1), (0, 1)];
+const FLAT_HEX_EVEN_NEIGHBOURS: [(i32, i32); 6] =
+    [(0, -1), (0, 1), (1, 0), (1, -1), (-1, 0), (-1, -1)];
+const FLAT_HEX_ODD_NEIGHBOURS: [(i32, i32); 6] =
+    [(0, -1), (0, 1), (1, 1), (1, 0), (-1, 1), (-1, 0)];
+
+impl GridLayout {
+    pub fn neighbours(&self, coord: GridCoord) -> Vec<GridCoord> {
+        let offsets: &[(i32, i32)] = match self {
+            GridLayout::Square | GridLayout::Isometric => &SQUARE_NEIGHBOURS,
+            GridLayout::PointyHex if coord.y & 1 == 0 => &POINTY_HEX_EVEN_NEIGHBOURS,
+            GridLayout::PointyHex => &POINTY_HEX_ODD_NEIGHBOURS,
+            GridLayout::FlatHex if coord.x & 1 == 0 => &FLAT_HEX_EVEN_NEIGHBOURS,
+            GridLayout::FlatHex => &FLAT_HEX_ODD_NEIGHBOURS,
+        };
+        offsets
+            .iter()
+            .map(|(dx, dy)| coord.offset(*dx, *dy))
+            .collect()
+    }
+
+    // Number of steps between two tiles, moving only between neighbours.
+    pub fn distance(&self, a: GridCoord, b: GridCoord) -> i32 {
+        match self {
+            GridLayout::Square | GridLayout::Isometric => (a.x - b.x).abs() + (a.y - b.y).abs(),
+            GridLayout::PointyHex | GridLayout::FlatHex => {
+                let (aq, ar) = self.to_axial(a);
+                let (bq, br) = self.to_axial(b);
+                let (dq, dr) = (aq - bq, ar - br);
+                (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+            }
+        }
+    }
+
+    fn to_axial(&self, coord: GridCoord) -> (i32, i32) {
+        match self {
+            GridLayout::PointyHex => (coord.x - (coord.y - (coord.y & 1)) / 2, coord.y),
+            GridLayout::FlatHex => (coord.x, coord.y - (coord.x - (coord.x & 1)) / 2),
+            GridLayout::Square | GridLayout::Isometric => (coord.x, coord.y),
+        }
+    }
+
+    // Centre of the tile relative to the centre of tile (0, 0).
+    pub fn to_world(&self, coord: GridCoord, tile_size: f32) -> Vector2 {
+        let (x, y) = (coord.x as f32, coord.y as f32);
+        match self {
+            GridLayout::Square => Vector2::new(x * tile_size, y * tile_size),
+            GridLayout::PointyHex => {
+                let shove = if coord.y & 1 == 1 { 0.5 } else { 0.0 };
+                Vector2::new((x + shove) * tile_size, y * tile_size * 0.75)
+            }
+            GridLayout::FlatHex => {
+                let shove = if coord.x & 1 == 1 { 0.5 } else { 0.0 };
+                Vector2::new(x * tile_size * 0.75, (y + shove) * tile_size)
+            }
+            GridLayout::Isometric => {
+                Vector2::new((x - y) * tile_size * 0.5, (x + y) * tile_size * 0.25)
+            }
+        }
+    }
+
+    // Corners of a tile's outline, clockwise from the top, relative to its centre.
+    pub fn outline(&self, tile_size: f32) -> Vec<Vector2> {
+        let h = tile_size * 0.5;
+        let q = tile_size * 0.25;
+        let corners: &[(f32, f32)] = match self {
+            GridLayout::Square => &[(-h, -h), (h, -h), (h, h), (-h, h)],
+            GridLayout::PointyHex => &[(0.0, -h), (h, -q), (h, q), (0.0, h), (-h, q), (-h, -q)],
+            GridLayout::FlatHex => &[(-q, -h), (q, -h), (h, 0.0), (q, h), (-q, h), (-h, 0.0)],
+            GridLayout::Isometric => &[(0.0, -q), (h, 0.0), (0.0, q), (-h, 0.0)],
+        };
+        corners.iter().map(|(x, y)| Vector2::new(*x, *y)).collect()
+    }
+
+    // Inverse of to_world: the tile whose centre is closest to the position.
+    pub fn from_world(&self, position: Vector2, tile_size: f32) -> GridCoord {
+        let (x, y) = (position.x / tile_size, position.y / tile_size);
+        let guess = match self {
+            GridLayout::Square => GridCoord::new(x.round() as i32, y.round() as i32),
+            GridLayout::PointyHex => {
+                let row = (y / 0.75).round() as i32;
+                let shove = if row & 1 == 1 { 0.5 } else { 0.0 };
+                GridCoord::new((x - shove).round() as i32, row)
+            }
+            GridLayout::FlatHex => {
+                let column = (x / 0.75).round() as i32;
+                let shove = if column & 1 == 1 { 0.5 } else { 0.0 };
+                GridCoord::new(column, (y - shove).round() as i32)
+            }
+            GridLayout::Isometric => {
+                GridCoord::new((x + 2.0 * y).round() as i32, (2.0 * y - x).round() as i32)
+            }
+        };
+        if let GridLayout::Square | GridLayout::Isometric = self {
+            return guess;
+        }
+
+        // Hexes don't tile the rounding boxes exactly, so settle it on the nearest centre.
+        let distance_to = |coord: &GridCoord| {
+            let centre = self.to_world(*coord, tile_size);
+            let (dx, dy) = (centre.x - position.x, centre.y - position.y);
+            dx * dx + dy * dy
+        };
+        let mut best = guess;
+        for candidate in self.neighbours(guess) {
+            if distance_to(&candidate) < distance_to(&best) {
+                best = candidate;
+            }
+        }
+        best
+    }
+}
+
+impl ToVariant for GridLayout {
+    fn to_variant(&self) -> Variant {
+        match self {
+            GridLayout::Square => 0.to_variant(),
+            GridLayout::PointyHex => 1.to_variant(),
+            GridLayout::FlatHex => 2.to_variant(),
+            GridLayout::Isometric => 3.to_variant(),
+        }
+    }
+}
+
+impl FromVariant for GridLayout {
+    fn from_variant(variant: &Variant) -> Result<Self, FromVariantError> {
+        let result = i64::from_variant(variant)?;
+        match result {
+            0 => Ok(GridLayout::Square),
+            1 => Ok(GridLayout::PointyHex),
+            2 => Ok(GridLayout::FlatHex),
+            3 => Ok(GridLayout::Isometric),
+            _ => Err(FromVariantError::UnknownEnumVariant {
+                variant: "i64".to_owned(),
+                expected: &["0", "1", "2", "3"],
+            }),
+        }
+    }
+}
+
+impl Export for GridLayout {
+    type Hint = IntHint<u32>;
+
+    fn export_info(_hint: Option<Self::Hint>) -> ExportInfo {
+        Self::Hint::Enum(EnumHint::new(vec![
+            "Square".to_owned(),
+            "PointyHex".to_owned(),
+            "FlatHex".to_owned(),
+            "Isometric".to_owned(),
+        ]))
+        .export_info()
+    }
+}
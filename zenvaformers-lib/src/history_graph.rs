@@ -0,0 +1,156 @@
+use gdnative::{api::Font, prelude::*};
+
+use crate::i18n::tr;
+use crate::{ResourceAmounts, ResourceType, TurnSnapshot};
+
+const AXIS: Color = Color {
+    r: 0.6,
+    g: 0.6,
+    b: 0.6,
+    a: 1.0,
+};
+// Space left of each chart for its scale, and above it for its title.
+const LEFT_MARGIN: f32 = 40.0;
+const TITLE_HEIGHT: f32 = 18.0;
+
+// Line charts of every turn so far: stockpiles on top, income underneath. Each resource
+// can be hidden on its own.
+#[derive(NativeClass)]
+#[inherit(Control)]
+pub struct HistoryGraph {
+    history: Vec<TurnSnapshot>,
+    hidden: Vec<ResourceType>,
+}
+
+#[methods]
+impl HistoryGraph {
+    fn new(_base: &Control) -> Self {
+        HistoryGraph {
+            history: Vec::new(),
+            hidden: Vec::new(),
+        }
+    }
+
+    #[method]
+    fn set_history(&mut self, #[base] base: &Control, history: Vec<TurnSnapshot>) {
+        self.history = history;
+        base.update();
+    }
+
+    #[method]
+    fn set_resource_visible(
+        &mut self,
+        #[base] base: &Control,
+        resource_type: ResourceType,
+        visible: bool,
+    ) {
+        self.hidden.retain(|t| *t != resource_type);
+        if !visible {
+            self.hidden.push(resource_type);
+        }
+        base.update();
+    }
+
+    #[method]
+    fn _draw(&self, #[base] base: &Control) {
+        let font = match base.get_font("font", "") {
+            Some(font) => font,
+            None => return,
+        };
+        let size = base.size();
+        if self.history.len() < 2 {
+            base.draw_string(
+                font,
+                Vector2::new(0.0, 16.0),
+                tr("Not enough turns yet"),
+                AXIS,
+                -1,
+            );
+            return;
+        }
+
+        let half = size.y / 2.0;
+        let top = Rect2::new(Vector2::ZERO, Vector2::new(size.x, half));
+        let bottom = Rect2::new(Vector2::new(0.0, half), Vector2::new(size.x, half));
+        self.draw_chart(base, &font, top, &tr("Stockpile"), |s| &s.stock);
+        self.draw_chart(base, &font, bottom, &tr("Income per turn"), |s| &s.income);
+    }
+
+    fn draw_chart(
+        &self,
+        base: &Control,
+        font: &Ref<Font>,
+        area: Rect2,
+        title: &str,
+        values: impl Fn(&TurnSnapshot) -> &ResourceAmounts,
+    ) {
+        let shown: Vec<&ResourceType> = ResourceType::STORED
+            .iter()
+            .filter(|t| !self.hidden.contains(*t))
+            .collect();
+        // Always include 0, so income charts show which side of it they are on.
+        let (mut min, mut max) = (0, 1);
+        for snapshot in self.history.iter() {
+            for resource_type in shown.iter() {
+                let value = values(snapshot).get(resource_type);
+                min = min.min(value);
+                max = max.max(value);
+            }
+        }
+
+        base.draw_string(
+            font.clone(),
+            area.position + Vector2::new(0.0, 14.0),
+            title,
+            AXIS,
+            -1,
+        );
+        let chart = Rect2::new(
+            area.position + Vector2::new(LEFT_MARGIN, TITLE_HEIGHT),
+            area.size - Vector2::new(LEFT_MARGIN, TITLE_HEIGHT + 4.0),
+        );
+        let last = (self.history.len() - 1) as f32;
+        let to_point = |index: usize, value: i32| {
+            let x = chart.position.x + chart.size.x * index as f32 / last;
+            let y = chart.position.y + chart.size.y * (max - value) as f32 / (max - min) as f32;
+            Vector2::new(x, y)
+        };
+
+        // Scale and the zero line.
+        let scale_x = area.position.x;
+        base.draw_string(
+            font.clone(),
+            Vector2::new(scale_x, to_point(0, max).y + 10.0),
+            max.to_string(),
+            AXIS,
+            -1,
+        );
+        base.draw_string(
+            font.clone(),
+            Vector2::new(scale_x, to_point(0, min).y),
+            min.to_string(),
+            AXIS,
+            -1,
+        );
+        base.draw_line(
+            to_point(0, 0),
+            to_point(self.history.len() - 1, 0),
+            AXIS,
+            1.0,
+            false,
+        );
+
+        for resource_type in shown {
+            let color = resource_type.get_color();
+            let points: Vec<Vector2> = self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(i, s)| to_point(i, values(s).get(resource_type)))
+                .collect();
+            for pair in points.windows(2) {
+                base.draw_line(pair[0], pair[1], color, 2.0, true);
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use gdnative::{
+    api::{ConfigFile, GlobalConstants, InputEventJoypadButton, InputEventKey, InputMap},
+    prelude::*,
+};
+
+use crate::i18n::{tr, tr_args};
+use crate::BuildingType;
+
+// Where remapped keys are kept between runs.
+pub const BINDINGS_PATH: &str = "user://keybindings.cfg";
+const SECTION: &str = "keys";
+
+pub const END_TURN: &str = "end_turn";
+pub const UNDO: &str = "undo";
+pub const SCOUT: &str = "scout";
+
+// One remappable action, as listed on the settings screen.
+pub struct Hotkey {
+    pub action: String,
+    pub label: String,
+    // Key it gets when project.godot doesn't define the action, with any modifier masks.
+    pub default_key: Option<i64>,
+    // Gamepad button it gets then too. These aren't remapped, so they stay as they are.
+    pub default_button: Option<i64>,
+}
+
+impl Hotkey {
+    fn new(
+        action: &str,
+        label: &str,
+        default_key: Option<i64>,
+        default_button: Option<i64>,
+    ) -> Self {
+        Hotkey {
+            action: action.to_owned(),
+            label: tr(label),
+            default_key,
+            default_button,
+        }
+    }
+}
+
+pub fn building_action(building_type: &BuildingType) -> String {
+    format!(
+        "build_{}",
+        building_type.name().to_lowercase().replace(' ', "_")
+    )
+}
+
+// 1 to 9 for the first nine, nothing after that.
+fn digit_key(index: usize) -> Option<i64> {
+    (index < 9).then(|| GlobalConstants::KEY_1 + index as i64)
+}
+
+// The gamepad buttons project.godot leaves free (A confirms, B cancels and the d-pad moves
+// the cursor), handed out in the same order as digit_key.
+const PAD_BUTTONS: [i64; 6] = [
+    GlobalConstants::JOY_XBOX_X,
+    GlobalConstants::JOY_XBOX_Y,
+    GlobalConstants::JOY_L,
+    GlobalConstants::JOY_R,
+    GlobalConstants::JOY_L2,
+    GlobalConstants::JOY_R2,
+];
+
+fn pad_button(index: usize) -> Option<i64> {
+    PAD_BUTTONS.get(index).copied()
+}
+
+// Every action the settings screen offers. Buildings come from BuildingType, numbered
+// 1, 2, 3... in order with Scout after them, so new ones get a key without anything
+// else changing.
+pub fn all() -> Vec<Hotkey> {
+    let mut hotkeys: Vec<Hotkey> = BuildingType::ALL
+        .iter()
+        .filter(|t| t.is_placeable())
+        .enumerate()
+        .map(|(index, t)| {
+            Hotkey::new(
+                &building_action(t),
+                &tr_args("Build {building}", &[("building", &tr(t.name()))]),
+                digit_key(index),
+                pad_button(index),
+            )
+        })
+        .collect();
+    let count = hotkeys.len();
+    hotkeys.push(Hotkey::new(
+        SCOUT,
+        "Scout",
+        digit_key(count),
+        pad_button(count),
+    ));
+    hotkeys.push(Hotkey::new(
+        END_TURN,
+        "End turn",
+        Some(GlobalConstants::KEY_E),
+        Some(GlobalConstants::JOY_START),
+    ));
+    hotkeys.push(Hotkey::new(
+        UNDO,
+        "Undo",
+        Some(GlobalConstants::KEY_MASK_CTRL | GlobalConstants::KEY_Z),
+        Some(GlobalConstants::JOY_SELECT),
+    ));
+    // The rest are defined in project.godot, along with their gamepad buttons.
+    hotkeys.push(Hotkey::new("cancel", "Cancel", None, None));
+    hotkeys.push(Hotkey::new("cursor_confirm", "Cursor confirm", None, None));
+    hotkeys.push(Hotkey::new("cursor_up", "Cursor up", None, None));
+    hotkeys.push(Hotkey::new("cursor_down", "Cursor down", None, None));
+    hotkeys.push(Hotkey::new("cursor_left", "Cursor left", None, None));
+    hotkeys.push(Hotkey::new("cursor_right", "Cursor right", None, None));
+    hotkeys.push(Hotkey::new("camera_up", "Camera up", None, None));
+    hotkeys.push(Hotkey::new("camera_down", "Camera down", None, None));
+    hotkeys.push(Hotkey::new("camera_left", "Camera left", None, None));
+    hotkeys.push(Hotkey::new("camera_right", "Camera right", None, None));
+    hotkeys
+}
+
+fn key_event(key: i64) -> Ref<InputEventKey, Unique> {
+    let event = InputEventKey::new();
+    event.set_scancode(key & GlobalConstants::KEY_CODE_MASK);
+    event.set_control(key & GlobalConstants::KEY_MASK_CTRL != 0);
+    event.set_shift(key & GlobalConstants::KEY_MASK_SHIFT != 0);
+    event.set_alt(key & GlobalConstants::KEY_MASK_ALT != 0);
+    event.set_metakey(key & GlobalConstants::KEY_MASK_META != 0);
+    event
+}
+
+fn button_event(button: i64) -> Ref<InputEventJoypadButton, Unique> {
+    let event = InputEventJoypadButton::new();
+    event.set_button_index(button);
+    event
+}
+
+fn key_events(action: &str) -> Vec<Ref<InputEventKey>> {
+    InputMap::godot_singleton()
+        .get_action_list(action)
+        .iter()
+        .filter_map(|e| e.to_object::<InputEventKey>())
+        .collect()
+}
+
+// Adds the actions project.godot doesn't know about, then applies the saved keys.
+pub fn load() {
+    add_missing_actions();
+    let config = ConfigFile::new();
+    if config.load(BINDINGS_PATH).is_err() {
+        return;
+    }
+    for hotkey in all() {
+        let saved = config.get_value(SECTION, hotkey.action.as_str(), Variant::nil());
+        if let Ok(keys) = Vec::<i64>::from_variant(&saved) {
+            bind(&hotkey.action, &keys);
+        }
+    }
+}
+
+pub fn save() {
+    let config = ConfigFile::new();
+    for hotkey in all() {
+        let keys: Vec<i64> = key_events(&hotkey.action)
+            .iter()
+            .map(|e| unsafe { e.assume_safe() }.get_scancode_with_modifiers())
+            .collect();
+        config.set_value(SECTION, hotkey.action.as_str(), keys);
+    }
+    if let Err(e) = config.save(BINDINGS_PATH) {
+        godot_error!("Could not save key bindings to {}: {:?}", BINDINGS_PATH, e);
+    }
+}
+
+// Replaces the action's keys with these. Mouse and gamepad buttons bound to it stay.
+pub fn bind(action: &str, keys: &[i64]) {
+    let input_map = InputMap::godot_singleton();
+    for event in key_events(action) {
+        input_map.action_erase_event(action, event);
+    }
+    for key in keys {
+        input_map.action_add_event(action, key_event(*key));
+    }
+}
+
+// Back to project.godot and the defaults above, forgetting anything saved.
+pub fn reset() {
+    InputMap::godot_singleton().load_from_globals();
+    add_missing_actions();
+    save();
+}
+
+fn add_missing_actions() {
+    let input_map = InputMap::godot_singleton();
+    for hotkey in all() {
+        if !input_map.has_action(hotkey.action.as_str()) {
+            input_map.add_action(hotkey.action.as_str(), 0.5);
+            if let Some(key) = hotkey.default_key {
+                input_map.action_add_event(hotkey.action.as_str(), key_event(key));
+            }
+            if let Some(button) = hotkey.default_button {
+                input_map.action_add_event(hotkey.action.as_str(), button_event(button));
+            }
+        }
+    }
+}
+
+// What the settings screen shows for an action, e.g. "Control+Z" or "Up, W".
+pub fn key_text(action: &str) -> String {
+    let keys: Vec<String> = key_events(action)
+        .iter()
+        .map(|e| unsafe { e.assume_safe() }.as_text().to_string())
+        .collect();
+    if keys.is_empty() {
+        tr("Unbound")
+    } else {
+        keys.join(", ")
+    }
+}
@@ -0,0 +1,70 @@
+use std::fmt::Display;
+
+use gdnative::{
+    api::{ConfigFile, TranslationServer},
+    prelude::*,
+};
+
+// Player-visible text is looked up by its English wording in Localization/strings.csv,
+// so anything a language is missing falls back to the English written in the code.
+// Placeholders are named, like "Turn: {turn}", since word order differs between languages.
+
+// Locale and the name the language picker shows for it, in its own language.
+pub const LANGUAGES: [(&str, &str); 2] = [("en", "English"), ("de", "Deutsch")];
+
+const SETTINGS_PATH: &str = "user://settings.cfg";
+const SECTION: &str = "locale";
+
+pub fn tr(key: &str) -> String {
+    TranslationServer::godot_singleton()
+        .translate(key)
+        .to_string()
+}
+
+// tr, then each {name} replaced with its value.
+pub fn tr_args(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut text = tr(key);
+    for (name, value) in args {
+        text = text.replace(&format!("{{{}}}", name), &value.to_string());
+    }
+    text
+}
+
+// Index into LANGUAGES of the language in use, English if it isn't one of them.
+pub fn current_language() -> usize {
+    let locale = TranslationServer::godot_singleton()
+        .get_locale()
+        .to_string();
+    LANGUAGES
+        .iter()
+        .position(|(code, _)| locale.starts_with(code))
+        .unwrap_or(0)
+}
+
+pub fn set_language(index: usize) {
+    let code = match LANGUAGES.get(index) {
+        Some((code, _)) => *code,
+        None => return,
+    };
+    TranslationServer::godot_singleton().set_locale(code);
+
+    let config = ConfigFile::new();
+    // Keep whatever else is in there.
+    let _ = config.load(SETTINGS_PATH);
+    config.set_value(SECTION, "language", code);
+    if let Err(e) = config.save(SETTINGS_PATH) {
+        godot_error!("Could not save the language to {}: {:?}", SETTINGS_PATH, e);
+    }
+}
+
+// Switches to the language picked last time, if there is one.
+pub fn load_language() {
+    let config = ConfigFile::new();
+    if config.load(SETTINGS_PATH).is_err() {
+        return;
+    }
+    let saved = config.get_value(SECTION, "language", Variant::nil());
+    if let Ok(code) = String::from_variant(&saved) {
+        TranslationServer::godot_singleton().set_locale(code);
+    }
+}
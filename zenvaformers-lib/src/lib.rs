@@ -1,5 +1,29 @@
+mod camera;
+mod event_log;
+mod grid;
+mod history_graph;
+mod hotkeys;
+mod i18n;
+mod map_editor;
+mod map_file;
+mod map_grid;
+mod minimap;
+mod noise;
+mod power;
+mod tile_cursor;
+mod validation;
+mod yields;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
 use gdnative::{
-    api::{Area2D, HBoxContainer, InputEventMouseButton},
+    api::{
+        Area2D, BoxContainer, CheckBox, CollisionPolygon2D, ColorRect, DynamicFont, Engine,
+        GlobalConstants, GridContainer, HBoxContainer, InputEventKey, InputEventMouseButton,
+        MenuButton, OptionButton, PackedScene, PanelContainer, Polygon2D, PopupMenu, TextureRect,
+        VBoxContainer, OS,
+    },
     export::{
         hint::{EnumHint, IntHint},
         Export,
@@ -7,38 +31,105 @@ use gdnative::{
     prelude::*,
 };
 
+use camera::CameraController;
+use event_log::{LogEntry, LogKind};
+use grid::{GridCoord, GridLayout};
+use history_graph::HistoryGraph;
+use i18n::{tr, tr_args};
+use map_editor::MapEditor;
+use map_grid::{MapGrid, TileState};
+use minimap::Minimap;
+use power::PowerGrid;
+use tile_cursor::TileCursor;
+use validation::{Diagnostic, SceneValidator};
+use yields::BuildingYield;
+
+// A Tile only draws the TileState that Map hands it and forwards clicks back.
 #[derive(NativeClass)]
 #[inherit(Area2D)]
 pub struct Tile {
-    #[property(default = false)]
-    start_tile: bool,
-    #[property(default = false)]
-    has_building: bool,
-    #[property(default = false)]
-    can_place_building: bool,
+    #[property(default = 0)]
+    grid_x: i32,
+    #[property(default = 0)]
+    grid_y: i32,
 }
 
 #[methods]
 impl Tile {
     fn new(_base: &Area2D) -> Self {
         Tile {
-            start_tile: false,
-            has_building: false,
-            can_place_building: false,
+            grid_x: 0,
+            grid_y: 0,
         }
     }
 
-    fn get_highlight(base: &Area2D) -> TRef<'static, Sprite> {
-        unsafe { base.get_node_as::<Sprite>("Highlight").unwrap() }
+    fn get_highlight(base: &Area2D) -> TRef<'static, Polygon2D> {
+        unsafe { base.get_node_as::<Polygon2D>("Highlight").unwrap() }
     }
 
     fn get_building_icon(base: &Area2D) -> TRef<'static, Sprite> {
         unsafe { base.get_node_as::<Sprite>("BuildingIcon").unwrap() }
     }
 
+    fn get_ground(base: &Area2D) -> TRef<'static, Polygon2D> {
+        unsafe { base.get_node_as::<Polygon2D>("Ground").unwrap() }
+    }
+
+    fn get_deposit_marker(base: &Area2D) -> TRef<'static, Sprite> {
+        unsafe { base.get_node_as::<Sprite>("DepositMarker").unwrap() }
+    }
+
+    fn get_overlay(base: &Area2D) -> TRef<'static, Polygon2D> {
+        unsafe { base.get_node_as::<Polygon2D>("Overlay").unwrap() }
+    }
+
+    fn get_overlay_label(base: &Area2D) -> TRef<'static, Label> {
+        unsafe { base.get_node_as::<Label>("OverlayLabel").unwrap() }
+    }
+
+    fn get_collision(base: &Area2D) -> TRef<'static, CollisionPolygon2D> {
+        unsafe { base.get_node_as::<CollisionPolygon2D>("Collision").unwrap() }
+    }
+
+    // Cuts the ground, highlight, overlay and mouse picking to the layout's tile shape,
+    // so neighbouring tiles neither overlap nor steal each other's clicks. The outline is
+    // in the TILE_SPRITE_SIZE space Tile.tscn is drawn in.
+    pub fn set_outline(&self, base: &Area2D, outline: Vec<Vector2>) {
+        let centre = Vector2::ONE * (TILE_SPRITE_SIZE * 0.5);
+        let uv: Vec<Vector2> = outline.iter().map(|p| *p + centre).collect();
+        let polygon = PoolArray::from_vec(outline.clone());
+        for textured in [Self::get_ground(base), Self::get_overlay(base)] {
+            textured.set_polygon(polygon.clone());
+            textured.set_uv(PoolArray::from_vec(uv.clone()));
+        }
+        Self::get_highlight(base).set_polygon(polygon.clone());
+        Self::get_collision(base).set_polygon(polygon);
+
+        // Icons shrink to the shorter side, which only matters for isometric diamonds.
+        let (mut min, mut max) = (Vector2::ZERO, Vector2::ZERO);
+        for p in outline.iter() {
+            min = Vector2::new(min.x.min(p.x), min.y.min(p.y));
+            max = Vector2::new(max.x.max(p.x), max.y.max(p.y));
+        }
+        let fit = (max.x - min.x).min(max.y - min.y) / TILE_SPRITE_SIZE;
+        Self::get_building_icon(base).set_scale(Vector2::ONE * fit);
+        // The deposit marker sits most of the way towards the top left corner.
+        let corner = outline
+            .iter()
+            .min_by(|a, b| (a.x + a.y).total_cmp(&(b.x + b.y)))
+            .cloned()
+            .unwrap_or(Vector2::ZERO);
+        Self::get_deposit_marker(base).set_position(corner * 0.7);
+    }
+
     #[method]
-    fn is_start_tile(&self, #[base] _base: &Area2D) -> bool {
-        self.start_tile
+    fn coord(&self, #[base] _base: &Area2D) -> GridCoord {
+        GridCoord::new(self.grid_x, self.grid_y)
+    }
+
+    fn set_coord(&mut self, coord: GridCoord) {
+        self.grid_x = coord.x;
+        self.grid_y = coord.y;
     }
 
     #[method]
@@ -47,28 +138,83 @@ impl Tile {
         base.add_to_group("Tiles", false);
     }
 
-    #[method]
-    fn toggle_highlight(&mut self, #[base] base: &Area2D, toggle: bool) {
-        Self::get_highlight(base).set_visible(toggle);
-        self.can_place_building = toggle;
+    // Called by Map whenever the state of this tile changes.
+    pub fn redraw(&self, base: &Area2D, state: &TileState) {
+        Self::get_ground(base).set_modulate(if state.explored {
+            state.terrain.get_color()
+        } else {
+            Color::from_rgb(0.12, 0.12, 0.16)
+        });
+        Self::get_highlight(base).set_visible(state.highlighted);
+
+        let marker = Self::get_deposit_marker(base);
+        marker.set_visible(state.explored && state.deposit != ResourceType::Nothing);
+        marker.set_modulate(state.deposit.get_color());
+
+        let icon = Self::get_building_icon(base);
+        match (&state.building, &state.ghost) {
+            (Some(building_type), _) => {
+                let t = load::<Texture>(building_type.get_texture_path()).unwrap();
+                icon.set_texture(t);
+                // Buildings cut off from the Base don't run, so show them greyed out.
+                icon.set_modulate(if state.connected {
+                    Color::from_rgb(1.0, 1.0, 1.0)
+                } else {
+                    Color::from_rgba(0.4, 0.4, 0.4, 0.8)
+                });
+            }
+            (None, Some(ghost)) => {
+                let t = load::<Texture>(ghost.get_texture_path()).unwrap();
+                icon.set_texture(t);
+                icon.set_modulate(Color::from_rgba(1.0, 1.0, 1.0, 0.5));
+            }
+            (None, None) => icon.set_texture(Null::null()),
+        }
+
+        let overlay = Self::get_overlay(base);
+        overlay.set_visible(state.overlay.is_some());
+        if let Some(color) = state.overlay {
+            overlay.set_modulate(Color::from_rgba(color.r, color.g, color.b, 0.45));
+        }
+        let label = Self::get_overlay_label(base);
+        label.set_visible(!state.overlay_text.is_empty());
+        label.set_text(state.overlay_text.as_str());
+    }
+
+    fn get_ui(base: &Area2D) -> Option<TRef<'static, Control>> {
+        unsafe { base.get_node_as::<Control>("/root/MainScene/HUD/UI") }
     }
 
+    fn get_game_manager(base: &Area2D) -> Option<TRef<'static, Node>> {
+        unsafe { base.get_node_as::<Node>("/root/MainScene") }
+    }
+
+    // Hovering is forwarded deferred, like clicks, since the ghost redraws this tile.
     #[method]
-    fn place_building(&mut self, #[base] base: &Area2D, building_type: BuildingType) {
-        godot_print!("In place building");
-        self.has_building = true;
-        let t = load::<Texture>(building_type.get_texture_path()).unwrap();
-        Self::get_building_icon(base).set_texture(t)
+    fn _on_tile_mouse_entered(&self, #[base] base: &Area2D) {
+        let coord = self.coord(base).to_variant();
+        if let Some(game_manager) = Self::get_game_manager(base) {
+            unsafe { game_manager.call_deferred("on_tile_hovered", &[coord.clone()]) };
+        }
+        if let Some(ui) = Self::get_ui(base) {
+            unsafe { ui.call_deferred("show_tile_tooltip", &[coord]) };
+        }
     }
 
     #[method]
-    fn has_building(&mut self, #[base] _base: &Area2D) -> bool {
-        self.has_building
+    fn _on_tile_mouse_exited(&self, #[base] base: &Area2D) {
+        let coord = self.coord(base).to_variant();
+        if let Some(game_manager) = Self::get_game_manager(base) {
+            unsafe { game_manager.call_deferred("on_tile_unhovered", &[coord.clone()]) };
+        }
+        if let Some(ui) = Self::get_ui(base) {
+            unsafe { ui.call_deferred("hide_tile_tooltip", &[coord]) };
+        }
     }
 
     #[method]
     fn _on_tile_input_event(
-        &mut self,
+        &self,
         #[base] base: &Area2D,
         viewport: Ref<Node>,
         event: Ref<InputEvent>,
@@ -76,155 +222,693 @@ impl Tile {
     ) {
         let event = unsafe { event.assume_safe() };
         if let Some(event) = event.cast::<InputEventMouseButton>() {
-            if event.is_pressed() {
+            // Right click cancels and the wheel zooms, neither should place anything.
+            if event.is_pressed() && event.button_index() == GlobalConstants::BUTTON_LEFT {
                 let game_manager = unsafe { base.get_node_as::<Node>("/root/MainScene").unwrap() };
-                let state = unsafe { game_manager.call("state", &[]) };
-                let state = GameManager::from_variant(&state).unwrap();
-
-                if self.can_place_building && state.is_placing_building {
-                    self.place_building(base, state.building_to_place);
-                    unsafe { game_manager.call("place_building", &[base.position().to_variant()]) };
+                // Deferred, since whatever the click does ends up redrawing this tile.
+                unsafe {
+                    game_manager.call_deferred("on_tile_clicked", &[self.coord(base).to_variant()])
                 };
             }
         }
     }
 }
 
+// Size in pixels of the sprites in Tile.tscn. Tiles are scaled from this to tile_size.
+const TILE_SPRITE_SIZE: f32 = 64.0;
+// How many tiles around itself a building explores, and how far a scout sees.
+const BUILDING_SIGHT: i32 = 2;
+const SCOUT_SIGHT: i32 = 1;
+
+// Registered as a tool class, so the Godot editor lays out the same Tile children the game
+// will, and lays them out again whenever the grid properties change in the inspector.
 #[derive(NativeClass)]
 #[inherit(Node)]
 pub struct Map {
-    // all_tiles: Vec<TRef<'static, Tile>>,
-    // tiles_with_buildings: Vec<TRef<'static, Tile>>,
-    #[property(default = 64.0)]
+    grid: MapGrid,
+    // Tile views, looked up by the coordinate they draw.
+    tiles: HashMap<GridCoord, Instance<Tile, Shared>>,
+    // Tiles redrawn since the last take_changed, for views that only catch up once a frame.
+    // A RefCell because redrawing only needs &self.
+    changed: RefCell<HashSet<GridCoord>>,
+    highlighted: Vec<GridCoord>,
+    // Tiles currently drawn with the power grid overlay.
+    overlaid: Vec<GridCoord>,
+    // Tile showing the building about to be placed, if any.
+    ghost: Option<GridCoord>,
+    #[property(default = 64.0, set = "Self::set_tile_size")]
     tile_size: f32,
+    // The editor previews this same seed, so what it shows is the map the game generates.
+    #[property(default = 0, set = "Self::set_seed")]
+    seed: i64,
+    #[property(default = 20, set = "Self::set_width")]
+    width: i32,
+    #[property(default = 9, set = "Self::set_height")]
+    height: i32,
+    #[property(set = "Self::set_layout")]
+    layout: GridLayout,
+    // Tiled .tmx, .csv or .png map to load instead of generating one.
+    #[property]
+    map_file: String,
+    // Start with every tile unexplored.
+    #[property(default = true)]
+    fog_of_war: bool,
+    // Draws the whole map as explored without changing it, for the MapEditor.
+    reveal_all: bool,
 }
 
 #[methods]
 impl Map {
     fn new(_base: &Node) -> Self {
         Map {
-            // These could be stored and used with on_ready for efficiency's sake
-            // all_tiles: Vec::new(),
-            // tiles_with_buildings: Vec::new(),
+            grid: MapGrid::new(0, 0),
+            tiles: HashMap::new(),
+            changed: RefCell::new(HashSet::new()),
+            highlighted: Vec::new(),
+            overlaid: Vec::new(),
+            ghost: None,
             tile_size: 64.0,
+            seed: 0,
+            width: 20,
+            height: 9,
+            layout: GridLayout::Square,
+            map_file: String::new(),
+            fog_of_war: true,
+            reveal_all: false,
+        }
+    }
+
+    fn set_tile_size(&mut self, base: TRef<Node>, tile_size: f32) {
+        self.tile_size = tile_size.max(1.0);
+        self.relayout_in_editor(&base);
+    }
+
+    fn set_seed(&mut self, base: TRef<Node>, seed: i64) {
+        self.seed = seed;
+        self.regenerate_in_editor(&base);
+    }
+
+    fn set_width(&mut self, base: TRef<Node>, width: i32) {
+        self.width = width.max(1);
+        self.regenerate_in_editor(&base);
+    }
+
+    fn set_height(&mut self, base: TRef<Node>, height: i32) {
+        self.height = height.max(1);
+        self.regenerate_in_editor(&base);
+    }
+
+    // The start tile depends on the layout's neighbours and distances, so it is chosen again.
+    fn set_layout(&mut self, base: TRef<Node>, layout: GridLayout) {
+        self.layout = layout;
+        self.regenerate_in_editor(&base);
+    }
+
+    // Setters also run while the scene is being loaded, before there is a tree to draw in.
+    fn is_editing(base: &Node) -> bool {
+        Engine::godot_singleton().is_editor_hint() && base.is_inside_tree()
+    }
+
+    fn regenerate_in_editor(&mut self, base: &Node) {
+        if Self::is_editing(base) {
+            self.reset_grid();
+            self.spawn_tiles(base);
+        }
+    }
+
+    // Moves the existing tiles rather than rebuilding them; the grid itself is unchanged.
+    fn relayout_in_editor(&mut self, base: &Node) {
+        if !Self::is_editing(base) {
+            return;
+        }
+        for (coord, tile) in self.tiles.iter() {
+            unsafe { tile.assume_safe() }
+                .map(|t, tile_base| {
+                    tile_base.set_position(self.grid_to_world(base, *coord));
+                    tile_base.set_scale(self.tile_scale());
+                    t.set_outline(&tile_base, self.layout.outline(TILE_SPRITE_SIZE));
+                })
+                .unwrap();
         }
     }
 
+    fn tile_scale(&self) -> Vector2 {
+        Vector2::ONE * (self.tile_size / TILE_SPRITE_SIZE)
+    }
+
     #[method]
-    fn _ready(&self, #[base] base: &Node) {
+    fn _ready(&mut self, #[base] base: &Node) {
         godot_print!("Hello from Map!");
-        // Should create all_tiles here. not convinced tiles_with_buildings is needed.
-        for tile in base.get_children().iter() {
-            let tile = unsafe { tile.try_to_object::<Area2D>().unwrap().assume_safe() };
-            let is_start_tile = unsafe { tile.call("is_start_tile", &[]) };
-            let is_start_tile = bool::from_variant(&is_start_tile).unwrap();
-            if is_start_tile {
-                godot_print!("Found start_tile");
-                unsafe { tile.call("place_building", &[BuildingType::Base.to_variant()]) };
-                break;
+        self.reset_grid();
+        self.spawn_tiles(base);
+        validation::report(&base.get_path().to_string(), &self.diagnostics(base));
+        if Engine::godot_singleton().is_editor_hint() {
+            return;
+        }
+
+        match self.grid.start_tile() {
+            Some(coord) => {
+                godot_print!("Found start_tile at {:?}", coord);
+                self.place_building(base, coord, BuildingType::Base);
+            }
+            None => godot_warn!("Map has no start_tile"),
+        }
+    }
+
+    fn reset_grid(&mut self) {
+        self.grid = self.load_grid();
+        // The Godot editor always shows the whole map.
+        if !self.fog_of_war || Engine::godot_singleton().is_editor_hint() {
+            self.grid.explore_all();
+        }
+        let buildings: Vec<GridCoord> = self.grid.buildings().map(|(c, _)| c).collect();
+        for coord in buildings {
+            self.grid.explore(coord, BUILDING_SIGHT, self.layout);
+        }
+    }
+
+    // Imports map_file when it is set, falling back to generating from the seed.
+    fn load_grid(&mut self) -> MapGrid {
+        if !self.map_file.is_empty() {
+            match map_file::import_map(&self.map_file) {
+                Ok(imported) => {
+                    godot_print!("Loaded map from {}", self.map_file);
+                    if let Some(layout) = imported.layout {
+                        self.layout = layout;
+                    }
+                    let mut grid = imported.grid;
+                    grid.ensure_start_tile(self.layout);
+                    self.width = grid.width();
+                    self.height = grid.height();
+                    return grid;
+                }
+                Err(e) => godot_error!("Could not import map, generating one instead: {}", e),
             }
         }
+
+        godot_print!(
+            "Generating {}x{} map from seed {}",
+            self.width,
+            self.height,
+            self.seed
+        );
+        MapGrid::generate(self.width, self.height, self.seed, self.layout)
+    }
+
+    // Creates one Tile view per grid cell and indexes it by coordinate.
+    fn spawn_tiles(&mut self, base: &Node) {
+        self.clear_tiles(base);
+        self.grid.update_connectivity(self.layout);
+
+        let tile_scene = load::<PackedScene>("res://Tile.tscn").unwrap();
+        let tile_scene = unsafe { tile_scene.assume_safe() };
+        for coord in self.grid.coords() {
+            let tile = unsafe {
+                tile_scene
+                    .instance(PackedScene::GEN_EDIT_STATE_DISABLED)
+                    .unwrap()
+                    .assume_safe()
+            };
+            let tile = tile.cast::<Area2D>().unwrap();
+            tile.set_name(format!("Tile_{}_{}", coord.x, coord.y));
+            tile.set_position(self.grid_to_world(base, coord));
+            tile.set_scale(self.tile_scale());
+
+            let tile = tile.cast_instance::<Tile>().unwrap();
+            tile.map_mut(|t, tile_base| {
+                t.set_coord(coord);
+                t.set_outline(&tile_base, self.layout.outline(TILE_SPRITE_SIZE));
+            })
+            .unwrap();
+            base.add_child(tile.base(), false);
+            self.tiles.insert(coord, tile.claim());
+            self.notify(coord);
+        }
     }
 
+    // Tiles are never owned by the edited scene, so the editor doesn't save them into it.
+    fn clear_tiles(&mut self, base: &Node) {
+        for (_, tile) in self.tiles.drain() {
+            let tile = unsafe { tile.base().assume_safe() };
+            base.remove_child(tile);
+            tile.queue_free();
+        }
+        self.highlighted.clear();
+        self.overlaid.clear();
+        self.ghost = None;
+        self.changed.borrow_mut().clear();
+    }
+
+    // Works out again which buildings reach the Base, redrawing the ones that changed.
+    fn update_connectivity(&mut self) {
+        for coord in self.grid.update_connectivity(self.layout) {
+            self.notify(coord);
+        }
+    }
+
+    fn explore(&mut self, centre: GridCoord, radius: i32) {
+        for coord in self.grid.explore(centre, radius, self.layout) {
+            self.notify(coord);
+        }
+    }
+
+    // Redraws the Tile view of a coordinate from the grid.
+    fn notify(&self, coord: GridCoord) {
+        if let (Some(tile), Some(state)) = (self.tiles.get(&coord), self.visible_state(coord)) {
+            unsafe { tile.assume_safe() }
+                .map(|t, tile_base| t.redraw(&tile_base, &state))
+                .unwrap();
+            self.changed.borrow_mut().insert(coord);
+        }
+    }
+
+    // The state a tile should be drawn with, which is all of it while reveal_all is on.
+    pub fn visible_state(&self, coord: GridCoord) -> Option<TileState> {
+        let state = self.grid.get(coord)?;
+        if self.reveal_all && !state.explored {
+            Some(TileState {
+                explored: true,
+                ..state.clone()
+            })
+        } else {
+            Some(state.clone())
+        }
+    }
+
+    pub fn take_changed(&self) -> Vec<GridCoord> {
+        self.changed.borrow_mut().drain().collect()
+    }
+
+    // Everything wrong with the map and the nodes under it. Never panics on a broken scene.
+    pub fn diagnostics(&self, base: &Node) -> Vec<Diagnostic> {
+        let map_path = base.get_path().to_string();
+        let mut diagnostics = validation::validate_grid(&self.grid, &map_path);
+
+        for child in base.get_children().iter() {
+            let child = match child.try_to_object::<Node>() {
+                Some(child) => unsafe { child.assume_safe() },
+                None => continue,
+            };
+            let path = child.get_path().to_string();
+            let tile = match child
+                .cast::<Area2D>()
+                .and_then(|a| a.cast_instance::<Tile>())
+            {
+                Some(tile) => tile,
+                None => {
+                    diagnostics.push(Diagnostic::new(
+                        path,
+                        format!("is a {}, not a Tile", child.get_class()),
+                    ));
+                    continue;
+                }
+            };
+
+            let coord = tile.map(|t, tile_base| t.coord(&tile_base)).unwrap();
+            if !self.grid.in_bounds(coord) {
+                diagnostics.push(Diagnostic::new(
+                    path,
+                    format!(
+                        "has coordinate {:?}, outside the {}x{} map",
+                        coord,
+                        self.grid.width(),
+                        self.grid.height()
+                    ),
+                ));
+                continue;
+            }
+            let managed = self
+                .tiles
+                .get(&coord)
+                .map(|t| {
+                    unsafe { t.base().assume_safe() }.get_instance_id()
+                        == tile.base().get_instance_id()
+                })
+                .unwrap_or(false);
+            if !managed {
+                diagnostics.push(Diagnostic::new(
+                    path.clone(),
+                    format!("duplicates the tile at {:?} and will never be drawn", coord),
+                ));
+            }
+            let expected = self.grid_to_world(base, coord);
+            let actual = tile.base().position();
+            if (actual - expected).length() > 0.5 {
+                diagnostics.push(Diagnostic::new(
+                    path,
+                    format!(
+                        "sits at ({}, {}) but {:?} is at ({}, {})",
+                        actual.x, actual.y, coord, expected.x, expected.y
+                    ),
+                ));
+            }
+        }
+        diagnostics
+    }
+
+    // For scripts and the SceneValidator: one "<node path>: <reason>" line per problem.
     #[method]
-    fn get_tile_at_position(
-        &self,
-        #[base] base: &Node,
-        position: Vector2,
-    ) -> Option<TRef<'static, Area2D>> {
-        for tile in base.get_children().iter() {
-            let tile = unsafe { tile.try_to_object::<Area2D>().unwrap().assume_safe() };
-            let has_building = unsafe { tile.call("has_building", &[]) };
-            let has_building: bool = bool::from_variant(&has_building).unwrap();
-            if tile.position() == position && !has_building {
-                return Some(tile);
+    fn validate(&self, #[base] base: &Node) -> Vec<String> {
+        self.diagnostics(base)
+            .iter()
+            .map(|d| d.to_string())
+            .collect()
+    }
+
+    pub fn grid(&self) -> &MapGrid {
+        &self.grid
+    }
+
+    pub fn layout(&self) -> GridLayout {
+        self.layout
+    }
+
+    pub fn tile_size(&self) -> f32 {
+        self.tile_size
+    }
+
+    pub fn ghost(&self) -> Option<GridCoord> {
+        self.ghost
+    }
+
+    // Tints each power grid its own colour and labels one tile with its surplus or deficit.
+    pub fn show_power_grids(&mut self, grids: &[PowerGrid]) {
+        self.hide_power_grids();
+        for (index, power_grid) in grids.iter().enumerate() {
+            for coord in power_grid.tiles.iter() {
+                let state = self.grid.get_mut(*coord).unwrap();
+                state.overlay = Some(PowerGrid::color(index));
+                state.overlay_text = String::new();
+                self.overlaid.push(*coord);
+            }
+            if let Some(first) = power_grid.tiles.iter().min_by_key(|c| (c.y, c.x)) {
+                self.grid.get_mut(*first).unwrap().overlay_text =
+                    format!("{:+.1}", power_grid.surplus());
+            }
+        }
+        for coord in self.overlaid.clone() {
+            self.notify(coord);
+        }
+    }
+
+    pub fn hide_power_grids(&mut self) {
+        for coord in std::mem::take(&mut self.overlaid) {
+            if let Some(state) = self.grid.get_mut(coord) {
+                state.overlay = None;
+                state.overlay_text = String::new();
             }
+            self.notify(coord);
         }
-        None
+    }
+
+    // Shifts the layout so the whole map starts half a tile in from the top left corner.
+    fn origin(&self) -> Vector2 {
+        let corners = [
+            GridCoord::new(0, 0),
+            GridCoord::new(self.width - 1, 0),
+            GridCoord::new(0, self.height - 1),
+            GridCoord::new(self.width - 1, self.height - 1),
+        ];
+        let (min_x, min_y) = corners
+            .iter()
+            .map(|c| self.layout.to_world(*c, self.tile_size))
+            .fold((f32::MAX, f32::MAX), |(x, y), p| (x.min(p.x), y.min(p.y)));
+        Vector2::new(self.tile_size * 0.5 - min_x, self.tile_size * 0.5 - min_y)
     }
 
     #[method]
-    fn get_tile_path(&self, #[base] base: &Node, position: Vector2) -> Option<String> {
-        self.get_tile_at_position(base, position)
-            .map(|x| x.get_path().to_string())
+    fn grid_to_world(&self, #[base] _base: &Node, coord: GridCoord) -> Vector2 {
+        self.layout.to_world(coord, self.tile_size) + self.origin()
     }
 
+    // Area covered by the tiles, in the same space as grid_to_world.
     #[method]
-    fn disable_tile_highlights(&self, #[base] base: &Node) {
-        for tile in base.get_children().iter() {
-            let tile = unsafe { tile.try_to_object::<Area2D>().unwrap().assume_safe() };
-            unsafe { tile.call("toggle_highlight", &[false.to_variant()]) };
+    fn world_bounds(&self, #[base] base: &Node) -> Rect2 {
+        let half = self.tile_size * 0.5;
+        let (min, max) = self
+            .grid
+            .coords()
+            .map(|c| self.grid_to_world(base, c))
+            .fold(
+                (
+                    Vector2::new(f32::MAX, f32::MAX),
+                    Vector2::new(f32::MIN, f32::MIN),
+                ),
+                |(min, max), p| {
+                    (
+                        Vector2::new(min.x.min(p.x), min.y.min(p.y)),
+                        Vector2::new(max.x.max(p.x), max.y.max(p.y)),
+                    )
+                },
+            );
+        if min.x > max.x {
+            return Rect2::new(Vector2::ZERO, Vector2::ZERO);
         }
+        let min = min - Vector2::new(half, half);
+        Rect2::new(min, max + Vector2::new(half, half) - min)
     }
 
     #[method]
-    fn highlight_available_tiles(&self, #[base] base: &Node) {
-        // NOTE: In a real app, stuffing these all into vecs at "on_ready" is going to make more sense.
-        for tile in base
-            .get_children()
-            .iter()
-            .map(|x| unsafe { x.try_to_object::<Area2D>().unwrap().assume_safe() })
-            .filter(|x| bool::from_variant(&unsafe { x.call("has_building", &[]) }).unwrap())
-        {
-            // NOTE: Inelligant hacking below, and the variable names lie too!
-            // NOTE: Tiles need to be perfectly placed.
-            let north_pos = Vector2::new(
-                tile.position().x,
-                tile.position().y - (self.tile_size - 8.0),
-            );
-            let south_pos = Vector2::new(
-                tile.position().x,
-                tile.position().y + (self.tile_size - 2.0),
-            );
-            let east_pos = Vector2::new(tile.position().x + self.tile_size, tile.position().y);
-            let west_pos = Vector2::new(tile.position().x - self.tile_size, tile.position().y);
-
-            // Above was a mess, time for good old fashioned hard coding!
-            let north_tile = self.get_tile_at_position(base, north_pos);
-            let south_tile = self.get_tile_at_position(base, south_pos);
-            let east_tile = self.get_tile_at_position(base, east_pos);
-            let west_tile = self.get_tile_at_position(base, west_pos);
-
-            godot_print!(
-                "{:?}, {:?}, {:?}, {:?}",
-                north_pos,
-                south_pos,
-                east_pos,
-                west_pos
-            );
+    fn world_to_grid(&self, #[base] _base: &Node, position: Vector2) -> GridCoord {
+        self.layout
+            .from_world(position - self.origin(), self.tile_size)
+    }
 
-            Self::set_highlight_for_optional_tile(north_tile);
-            Self::set_highlight_for_optional_tile(south_tile);
-            Self::set_highlight_for_optional_tile(east_tile);
-            Self::set_highlight_for_optional_tile(west_tile);
+    #[method]
+    fn neighbours(&self, #[base] _base: &Node, coord: GridCoord) -> Vec<GridCoord> {
+        self.layout
+            .neighbours(coord)
+            .into_iter()
+            .filter(|n| self.grid.in_bounds(*n))
+            .collect()
+    }
+
+    #[method]
+    fn distance(&self, #[base] _base: &Node, from: GridCoord, to: GridCoord) -> i32 {
+        self.layout.distance(from, to)
+    }
+
+    #[method]
+    fn tile_state(&self, #[base] _base: &Node, coord: GridCoord) -> Option<TileState> {
+        self.grid.get(coord).cloned()
+    }
+
+    #[method]
+    fn can_place_building(&self, #[base] _base: &Node, coord: GridCoord) -> bool {
+        self.grid
+            .get(coord)
+            .map(|t| t.highlighted && t.is_buildable())
+            .unwrap_or(false)
+    }
+
+    #[method]
+    fn get_tile_at(&self, #[base] _base: &Node, coord: GridCoord) -> Option<Ref<Area2D>> {
+        self.tiles.get(&coord).map(|t| t.base().clone())
+    }
+
+    #[method]
+    fn get_tile_path(&self, #[base] _base: &Node, coord: GridCoord) -> Option<String> {
+        self.tiles
+            .get(&coord)
+            .map(|t| unsafe { t.base().assume_safe() }.get_path().to_string())
+    }
+
+    #[method]
+    fn show_ghost(&mut self, #[base] base: &Node, coord: GridCoord, building_type: BuildingType) {
+        self.hide_ghost(base);
+        if let Some(state) = self.grid.get_mut(coord) {
+            state.ghost = Some(building_type);
+            self.ghost = Some(coord);
+            self.notify(coord);
         }
     }
 
-    fn set_highlight_for_optional_tile(tile: Option<TRef<'static, Area2D>>) {
-        if let Some(t) = tile {
-            unsafe { t.call("toggle_highlight", &[true.to_variant()]) };
-        };
+    #[method]
+    fn hide_ghost(&mut self, #[base] _base: &Node) {
+        if let Some(coord) = self.ghost.take() {
+            self.grid.get_mut(coord).unwrap().ghost = None;
+            self.notify(coord);
+        }
+    }
+
+    #[method]
+    fn disable_tile_highlights(&mut self, #[base] base: &Node) {
+        self.hide_ghost(base);
+        for coord in std::mem::take(&mut self.highlighted) {
+            if let Some(state) = self.grid.get_mut(coord) {
+                state.highlighted = false;
+            }
+            self.notify(coord);
+        }
+    }
+
+    #[method]
+    fn highlight_available_tiles(&mut self, #[base] base: &Node) {
+        self.disable_tile_highlights(base);
+        self.highlighted = self.grid.available_for_building(self.layout);
+        for coord in self.highlighted.iter() {
+            self.grid.get_mut(*coord).unwrap().highlighted = true;
+            self.notify(*coord);
+        }
     }
 
     #[method]
     fn place_building(
-        &self,
+        &mut self,
         #[base] base: &Node,
-        tile_position: Vector2,
+        coord: GridCoord,
         building_type: BuildingType,
     ) {
+        if let Some(state) = self.grid.get_mut(coord) {
+            state.building = Some(building_type);
+            self.notify(coord);
+        }
+        self.explore(coord, BUILDING_SIGHT);
+        self.update_connectivity();
+        self.disable_tile_highlights(base);
+    }
+
+    #[method]
+    fn highlight_scout_targets(&mut self, #[base] base: &Node) {
+        self.disable_tile_highlights(base);
+        self.highlighted = self.grid.scout_targets(self.layout);
+        for coord in self.highlighted.iter() {
+            self.grid.get_mut(*coord).unwrap().highlighted = true;
+            self.notify(*coord);
+        }
+    }
+
+    #[method]
+    fn can_scout(&self, #[base] _base: &Node, coord: GridCoord) -> bool {
+        self.grid
+            .get(coord)
+            .map(|t| t.highlighted && !t.explored)
+            .unwrap_or(false)
+    }
+
+    #[method]
+    fn scout(&mut self, #[base] base: &Node, coord: GridCoord) {
+        self.explore(coord, SCOUT_SIGHT);
         self.disable_tile_highlights(base);
     }
+
+    #[method]
+    fn set_reveal_all(&mut self, #[base] _base: &Node, reveal_all: bool) {
+        self.reveal_all = reveal_all;
+        for coord in self.grid.coords().collect::<Vec<_>>() {
+            self.notify(coord);
+        }
+    }
+
+    // The methods below write map data for the MapEditor rather than playing the game.
+
+    #[method]
+    fn paint_terrain(&mut self, #[base] _base: &Node, coord: GridCoord, terrain: Terrain) {
+        let state = match self.grid.get_mut(coord) {
+            Some(state) => state,
+            None => return,
+        };
+        state.terrain = terrain;
+        self.notify(coord);
+        self.update_connectivity();
+    }
+
+    // Moves the start tile, and the Base along with it.
+    #[method]
+    fn set_start_tile(&mut self, #[base] _base: &Node, coord: GridCoord) {
+        if !self.grid.in_bounds(coord) {
+            return;
+        }
+        if let Some(old) = self.grid.start_tile() {
+            let state = self.grid.get_mut(old).unwrap();
+            state.start_tile = false;
+            if state.building == Some(BuildingType::Base) {
+                state.building = None;
+            }
+            self.notify(old);
+        }
+        let state = self.grid.get_mut(coord).unwrap();
+        state.start_tile = true;
+        state.building = Some(BuildingType::Base);
+        self.notify(coord);
+        self.explore(coord, BUILDING_SIGHT);
+        self.update_connectivity();
+    }
+
+    #[method]
+    fn place_prebuilt(
+        &mut self,
+        #[base] _base: &Node,
+        coord: GridCoord,
+        building_type: BuildingType,
+    ) {
+        if let Some(state) = self.grid.get_mut(coord) {
+            if !state.start_tile {
+                state.building = Some(building_type);
+                self.notify(coord);
+                self.explore(coord, BUILDING_SIGHT);
+                self.update_connectivity();
+            }
+        }
+    }
+
+    #[method]
+    fn remove_building(&mut self, #[base] _base: &Node, coord: GridCoord) {
+        if let Some(state) = self.grid.get_mut(coord) {
+            if state.start_tile {
+                godot_warn!("The Base stays on the start tile");
+                return;
+            }
+            state.building = None;
+            self.notify(coord);
+            self.update_connectivity();
+        }
+    }
+
+    pub fn save_map(&self, path: &str) -> Result<(), String> {
+        map_file::export_tmx(path, &self.grid, self.layout, self.tile_size)
+    }
 }
 
+// Debug menu ids. Editor tools use their index in EditorTool::all().
+const DEBUG_MENU_EDITOR: i64 = 100;
+const DEBUG_MENU_SAVE_MAP: i64 = 101;
+
+// Font for the names and costs under the building button icons.
+const BUILDING_BUTTON_FONT: &str = "res://Font/robotosmall.tres";
+const RESOURCE_PANEL_FONT: &str = "res://Font/robotomedium.tres";
+const RESOURCE_AMOUNT: Color = Color {
+    r: 0.866667,
+    g: 0.65098,
+    b: 0.0745098,
+    a: 1.0,
+};
+
 #[derive(NativeClass)]
 #[inherit(Control)]
-pub struct UI {}
+pub struct UI {
+    // The tile the tooltip is showing, if it is up.
+    tooltip_coord: Option<GridCoord>,
+    // One generated button per placeable building, in BuildingButtons.
+    building_buttons: Vec<(BuildingType, Ref<Button>)>,
+    // The amount and income labels of each ResourcePanel row.
+    resource_rows: Vec<(ResourceType, Ref<Label>, Ref<Label>)>,
+    // The settings screen button showing each action's keys.
+    key_buttons: Vec<(String, Ref<Button>)>,
+    // Action waiting for its new key, after its button was clicked.
+    rebinding: Option<String>,
+}
 
 #[methods]
 impl UI {
     fn new(_base: &Control) -> Self {
-        UI {}
+        UI {
+            tooltip_coord: None,
+            building_buttons: Vec::new(),
+            resource_rows: Vec::new(),
+            key_buttons: Vec::new(),
+            rebinding: None,
+        }
     }
 
     #[method]
@@ -236,13 +920,421 @@ impl UI {
     }
 
     #[method]
-    fn get_food_metal_text(&self, #[base] base: &Control) -> TRef<'static, Label> {
-        unsafe { base.get_node_as::<Label>("FoodMetalText").unwrap() }
+    fn show_building_buttons(&self, #[base] base: &Control) {
+        self.get_building_buttons(base).set_visible(true);
+    }
+
+    // One button per building BuildingData says can be placed, ahead of the Scout button.
+    fn build_building_buttons(&mut self, base: &Control) {
+        let row = self.get_building_buttons(base);
+        let scout = unsafe { row.get_node_as::<Button>("ScoutButton").unwrap() };
+        let font = load::<DynamicFont>(BUILDING_BUTTON_FONT).unwrap();
+        let buildings = unsafe { BuildingData::get_singleton_node(base).call("placeable", &[]) };
+        let buildings = Vec::<Building>::from_variant(&buildings).unwrap();
+
+        for building in buildings.iter() {
+            let building_type = building.building_type.clone();
+            let button = Button::new();
+            button.set_custom_minimum_size(Vector2::new(76.0, 75.0));
+            button.set_tooltip(Self::describe_building(building));
+
+            // The icon sits above the name and cost, which a Button can't lay out itself.
+            let column = VBoxContainer::new();
+            column.set_anchors_and_margins_preset(Control::PRESET_WIDE, 0, 0);
+            column.set_alignment(BoxContainer::ALIGN_CENTER);
+            column.add_constant_override("separation", 0);
+            column.set_mouse_filter(Control::MOUSE_FILTER_IGNORE);
+
+            let icon = TextureRect::new();
+            icon.set_texture(load::<Texture>(building_type.get_texture_path()).unwrap());
+            icon.set_expand(true);
+            icon.set_stretch_mode(TextureRect::STRETCH_KEEP_ASPECT_CENTERED);
+            icon.set_custom_minimum_size(Vector2::new(0.0, 40.0));
+            icon.set_mouse_filter(Control::MOUSE_FILTER_IGNORE);
+            column.add_child(icon, false);
+
+            for text in [tr(building_type.name()), Self::cost_text(building)] {
+                let label = Label::new();
+                label.set_text(text);
+                label.set_align(Label::ALIGN_CENTER);
+                label.add_font_override("font", font.clone());
+                label.set_mouse_filter(Control::MOUSE_FILTER_IGNORE);
+                column.add_child(label, false);
+            }
+
+            button.add_child(column, false);
+            let button = button.into_shared();
+            row.add_child(button.clone(), false);
+            row.move_child(button.clone(), scout.get_index());
+            unsafe { button.assume_safe() }
+                .connect(
+                    "pressed",
+                    unsafe { base.assume_shared() },
+                    "_on_building_button_pressed",
+                    VariantArray::from_iter([building_type.to_variant()]).into_shared(),
+                    0,
+                )
+                .unwrap();
+            self.building_buttons.push((building_type, button));
+        }
+    }
+
+    // Buildings cost nothing to place, so what they cost is their upkeep.
+    fn cost_text(building: &Building) -> String {
+        if building.upkeep_amount == 0 || building.upkeep_type == ResourceType::Nothing {
+            tr("Free")
+        } else {
+            tr_args(
+                "{amount} {resource}/turn",
+                &[
+                    ("amount", &building.upkeep_amount),
+                    ("resource", &tr(building.upkeep_type.name())),
+                ],
+            )
+        }
+    }
+
+    fn describe_building(building: &Building) -> String {
+        let mut lines = vec![
+            tr(building.building_type.name()),
+            tr(building.building_type.description()),
+        ];
+        if building.resource_type != ResourceType::Nothing {
+            lines.push(tr_args(
+                "Produces {amount} {resource} per turn",
+                &[
+                    ("amount", &building.resource_amount),
+                    ("resource", &tr(building.resource_type.name())),
+                ],
+            ));
+        }
+        if building.upkeep_type != ResourceType::Nothing {
+            lines.push(tr_args(
+                "Uses {amount} {resource} per turn",
+                &[
+                    ("amount", &building.upkeep_amount),
+                    ("resource", &tr(building.upkeep_type.name())),
+                ],
+            ));
+        }
+        lines.join("\n")
+    }
+
+    // One row per stored resource: colour swatch, name, amount / capacity and income.
+    fn build_resource_panel(&mut self, base: &Control) {
+        let panel = unsafe { base.get_node_as::<GridContainer>("ResourcePanel").unwrap() };
+        panel.set_columns(4);
+        let font = load::<DynamicFont>(RESOURCE_PANEL_FONT).unwrap();
+        let label = |text: &str| {
+            let label = Label::new();
+            label.set_text(text);
+            label.add_font_override("font", font.clone());
+            label.into_shared()
+        };
+
+        for resource_type in ResourceType::STORED {
+            let swatch = ColorRect::new();
+            swatch.set_frame_color(resource_type.get_color());
+            swatch.set_custom_minimum_size(Vector2::new(16.0, 16.0));
+            swatch.set_v_size_flags(Control::SIZE_SHRINK_CENTER);
+            panel.add_child(swatch, false);
+
+            let name = label(&tr_args(
+                "{resource}:",
+                &[("resource", &tr(resource_type.name()))],
+            ));
+            unsafe { name.assume_safe() }.set_align(Label::ALIGN_RIGHT);
+            panel.add_child(name, false);
+
+            let amount = label("");
+            unsafe { amount.assume_safe() }.add_color_override("font_color", RESOURCE_AMOUNT);
+            panel.add_child(amount.clone(), false);
+
+            let income = label("");
+            panel.add_child(income.clone(), false);
+            self.resource_rows.push((resource_type, amount, income));
+        }
+    }
+
+    fn get_income_breakdown(base: &Control) -> TRef<'static, Control> {
+        unsafe { base.get_node_as::<Control>("IncomeBreakdown").unwrap() }
+    }
+
+    // Lists every IncomeEntry under its resource. Entries with a tile focus the camera on it.
+    fn refresh_income_breakdown(&self, base: &Control) {
+        let list = unsafe {
+            base.get_node_as::<VBoxContainer>("IncomeBreakdown/Scroll/Entries")
+                .unwrap()
+        };
+        for child in list.get_children().iter() {
+            if let Some(child) = child.to_object::<Node>() {
+                let child = unsafe { child.assume_safe() };
+                list.remove_child(child);
+                child.queue_free();
+            }
+        }
+
+        let entries = unsafe { self.get_game_manager(base).call("income_breakdown", &[]) };
+        let entries = Vec::<IncomeEntry>::from_variant(&entries).unwrap_or_default();
+        let font = load::<DynamicFont>(BUILDING_BUTTON_FONT).unwrap();
+        for resource_type in ResourceType::STORED {
+            let entries: Vec<&IncomeEntry> = entries
+                .iter()
+                .filter(|e| e.resource_type == resource_type)
+                .collect();
+            let total: i32 = entries.iter().map(|e| e.amount).sum();
+
+            let header = Label::new();
+            header.set_text(format!(
+                "{} {:+}",
+                tr_args("{resource}:", &[("resource", &tr(resource_type.name()))]),
+                total
+            ));
+            header.add_color_override("font_color", resource_type.get_color());
+            list.add_child(header, false);
+            if entries.is_empty() {
+                let none = Label::new();
+                none.set_text(format!("  {}", tr("Nothing")));
+                none.add_font_override("font", font.clone());
+                list.add_child(none, false);
+            }
+
+            for entry in entries {
+                let button = Button::new();
+                let mut text = format!("{:+}  {}", entry.amount, entry.source);
+                if !entry.detail.is_empty() {
+                    text = format!("{}: {}", text, entry.detail);
+                }
+                button.set_text(text.as_str());
+                button.set_tooltip(text);
+                button.set_flat(true);
+                button.set_clip_text(true);
+                button.set_text_align(Button::ALIGN_LEFT);
+                button.add_font_override("font", font.clone());
+                match entry.coord {
+                    Some(coord) => {
+                        button
+                            .connect(
+                                "pressed",
+                                unsafe { base.assume_shared() },
+                                "_on_income_entry_pressed",
+                                VariantArray::from_iter([coord.to_variant()]).into_shared(),
+                                0,
+                            )
+                            .unwrap();
+                    }
+                    None => button.set_disabled(true),
+                }
+                list.add_child(button, false);
+            }
+        }
+    }
+
+    fn get_history_panel(base: &Control) -> TRef<'static, Control> {
+        unsafe { base.get_node_as::<Control>("HistoryPanel").unwrap() }
+    }
+
+    fn get_history_graph(base: &Control) -> TInstance<'static, HistoryGraph> {
+        unsafe {
+            base.get_node_as_instance::<HistoryGraph>("HistoryPanel/Content/Graph")
+                .unwrap()
+        }
+    }
+
+    // A check box per resource above the graph, all on to start with.
+    fn build_history_toggles(&self, base: &Control) {
+        let toggles = unsafe {
+            base.get_node_as::<HBoxContainer>("HistoryPanel/Content/Toggles")
+                .unwrap()
+        };
+        let font = load::<DynamicFont>(BUILDING_BUTTON_FONT).unwrap();
+        for resource_type in ResourceType::STORED {
+            let toggle = CheckBox::new();
+            toggle.set_text(tr(resource_type.name()));
+            toggle.set_pressed(true);
+            toggle.add_font_override("font", font.clone());
+            toggle.add_color_override("font_color", resource_type.get_color());
+            toggle
+                .connect(
+                    "toggled",
+                    unsafe { base.assume_shared() },
+                    "_on_history_toggle_toggled",
+                    VariantArray::from_iter([resource_type.to_variant()]).into_shared(),
+                    0,
+                )
+                .unwrap();
+            toggles.add_child(toggle, false);
+        }
+    }
+
+    fn refresh_history(&self, base: &Control) {
+        let history = unsafe { self.get_game_manager(base).call("history", &[]) };
+        let history = Vec::<TurnSnapshot>::from_variant(&history).unwrap_or_default();
+        Self::get_history_graph(base)
+            .map_mut(|g, graph_base| g.set_history(&graph_base, history))
+            .unwrap();
     }
 
     #[method]
-    fn get_oxygen_energy_text(&self, #[base] base: &Control) -> TRef<'static, Label> {
-        unsafe { base.get_node_as::<Label>("OxygenEnergyText").unwrap() }
+    fn _on_history_button_toggled(&self, #[base] base: &Control, pressed: bool) {
+        if pressed {
+            self.refresh_history(base);
+        }
+        Self::get_history_panel(base).set_visible(pressed);
+    }
+
+    #[method]
+    fn _on_history_toggle_toggled(
+        &self,
+        #[base] base: &Control,
+        pressed: bool,
+        resource_type: ResourceType,
+    ) {
+        Self::get_history_graph(base)
+            .map_mut(|g, graph_base| g.set_resource_visible(&graph_base, resource_type, pressed))
+            .unwrap();
+    }
+
+    fn get_event_log_list(base: &Control) -> TRef<'static, VBoxContainer> {
+        unsafe {
+            base.get_node_as::<VBoxContainer>("EventLog/Scroll/Entries")
+                .unwrap()
+        }
+    }
+
+    fn add_log_line(&self, base: &Control, entry: &LogEntry) {
+        let label = Label::new();
+        label.set_text(entry.line());
+        label.set_autowrap(true);
+        label.add_font_override("font", load::<DynamicFont>(BUILDING_BUTTON_FONT).unwrap());
+        label.add_color_override("font_color", entry.kind.get_color());
+        Self::get_event_log_list(base).add_child(label, false);
+        // Stay at the newest entry once the list has been laid out again.
+        let scroll = unsafe { base.get_node_as::<Node>("EventLog/Scroll").unwrap() };
+        unsafe { scroll.call_deferred("set_v_scroll", &[i32::MAX.to_variant()]) };
+    }
+
+    // Called deferred by GameManager for every new entry.
+    #[method]
+    fn on_log_entry(&self, #[base] base: &Control, entry: LogEntry) {
+        self.add_log_line(base, &entry);
+        if entry.kind.is_important() {
+            self.show_toast(base, &entry);
+        }
+    }
+
+    #[method]
+    fn rebuild_event_log(&self, #[base] base: &Control) {
+        let list = Self::get_event_log_list(base);
+        for child in list.get_children().iter() {
+            if let Some(child) = child.to_object::<Node>() {
+                let child = unsafe { child.assume_safe() };
+                list.remove_child(child);
+                child.queue_free();
+            }
+        }
+        let entries = unsafe { self.get_game_manager(base).call("event_log", &[]) };
+        for entry in Vec::<LogEntry>::from_variant(&entries)
+            .unwrap_or_default()
+            .iter()
+        {
+            self.add_log_line(base, entry);
+        }
+    }
+
+    // A short-lived note at the top of the screen, newest at the bottom.
+    fn show_toast(&self, base: &Control, entry: &LogEntry) {
+        let toasts = unsafe { base.get_node_as::<VBoxContainer>("Toasts").unwrap() };
+        while toasts.get_child_count() as usize >= event_log::MAX_TOASTS {
+            match toasts.get_child(0).map(|c| unsafe { c.assume_safe() }) {
+                Some(oldest) => {
+                    toasts.remove_child(oldest);
+                    oldest.queue_free();
+                }
+                None => break,
+            }
+        }
+
+        let toast = PanelContainer::new();
+        toast.set_mouse_filter(Control::MOUSE_FILTER_IGNORE);
+        let label = Label::new();
+        label.set_text(entry.text.as_str());
+        label.set_align(Label::ALIGN_CENTER);
+        label.add_color_override("font_color", entry.kind.get_color());
+        toast.add_child(label, false);
+        let toast = toast.into_shared();
+        toasts.add_child(toast.clone(), false);
+
+        let timer = base.get_tree().map(|tree| {
+            unsafe { tree.assume_safe() }.create_timer(event_log::TOAST_SECONDS, false)
+        });
+        if let Some(timer) = timer.flatten() {
+            unsafe { timer.assume_safe() }
+                .connect(
+                    "timeout",
+                    toast,
+                    "queue_free",
+                    VariantArray::new_shared(),
+                    0,
+                )
+                .unwrap();
+        }
+    }
+
+    #[method]
+    fn _on_log_button_toggled(&self, #[base] base: &Control, pressed: bool) {
+        unsafe { base.get_node_as::<Control>("EventLog").unwrap() }.set_visible(pressed);
+    }
+
+    #[method]
+    fn _on_income_button_toggled(&self, #[base] base: &Control, pressed: bool) {
+        if pressed {
+            self.refresh_income_breakdown(base);
+        }
+        Self::get_income_breakdown(base).set_visible(pressed);
+    }
+
+    #[method]
+    fn _on_income_entry_pressed(&self, #[base] base: &Control, coord: GridCoord) {
+        let position = Self::get_map(base)
+            .map(|m, map_base| m.grid_to_world(&map_base, coord))
+            .unwrap();
+        let camera = unsafe { base.get_node_as::<Node>("/root/MainScene/Camera").unwrap() };
+        unsafe { camera.call("focus_on", &[position.to_variant()]) };
+    }
+
+    // Called deferred by GameManager whenever a stockpile or income changes.
+    #[method]
+    fn update_resources(&self, #[base] base: &Control) {
+        let game_manager = self.get_game_manager(base);
+        let resources = unsafe { game_manager.call("resources", &[]) };
+        let resources = Vec::<ResourceStatus>::from_variant(&resources).unwrap_or_default();
+        for status in resources.iter() {
+            let row = self
+                .resource_rows
+                .iter()
+                .find(|r| r.0 == status.resource_type);
+            if let Some((_, amount, income)) = row {
+                let amount = unsafe { amount.assume_safe() };
+                amount.set_text(format!("{} / {}", status.amount, status.capacity));
+                let income = unsafe { income.assume_safe() };
+                income.set_text(format!("{:+}", status.income));
+                income.add_color_override(
+                    "font_color",
+                    match status.income.signum() {
+                        1 => Color::from_rgb(0.45, 0.85, 0.4),
+                        -1 => Color::from_rgb(0.95, 0.4, 0.35),
+                        _ => Color::from_rgb(0.7, 0.7, 0.7),
+                    },
+                );
+            }
+        }
+        if Self::get_income_breakdown(base).is_visible() {
+            self.refresh_income_breakdown(base);
+        }
+        if Self::get_history_panel(base).is_visible() {
+            self.refresh_history(base);
+        }
     }
 
     #[method]
@@ -255,52 +1347,277 @@ impl UI {
         unsafe { base.get_node_as::<Node>("/root/MainScene").unwrap() }
     }
 
+    fn get_map_editor(base: &Control) -> TInstance<'static, MapEditor> {
+        unsafe {
+            base.get_node_as_instance::<MapEditor>("/root/MainScene/MapEditor")
+                .unwrap()
+        }
+    }
+
+    fn get_map(base: &Control) -> TInstance<'static, Map> {
+        unsafe {
+            base.get_node_as_instance::<Map>("/root/MainScene/Tiles")
+                .unwrap()
+        }
+    }
+
+    fn get_tile_tooltip(base: &Control) -> TRef<'static, Control> {
+        unsafe { base.get_node_as::<Control>("TileTooltip").unwrap() }
+    }
+
+    fn get_debug_menu(base: &Control) -> TRef<'static, PopupMenu> {
+        let menu = unsafe { base.get_node_as::<MenuButton>("DebugMenu").unwrap() };
+        unsafe { menu.get_popup().unwrap().assume_safe() }
+    }
+
     #[method]
-    fn _ready(&self, #[base] _base: &Control) {
-        godot_print!("Hello from UI!")
+    fn _ready(&mut self, #[base] base: &Control) {
+        godot_print!("Hello from UI!");
+        // Before anything builds its text.
+        i18n::load_language();
+        hotkeys::load();
+        self.build_debug_menu(base);
+        self.build_building_buttons(base);
+        self.build_resource_panel(base);
+        self.build_history_toggles(base);
+        self.build_keys_panel(base);
+        self.build_language_button(base);
+
+        // The overlay only means something when energy flows through power grids.
+        let power_grid =
+            bool::from_variant(&self.get_game_manager(base).get("power_grid")).unwrap_or(false);
+        let overlay_button = unsafe { base.get_node_as::<Button>("PowerOverlayButton").unwrap() };
+        overlay_button.set_visible(power_grid);
     }
 
     #[method]
-    fn on_end_turn(&self, #[base] base: &Control, state: GameManager) {
-        let turn_text = self.get_turn_text(base);
-        turn_text.set_text(format!("Turn: {}", state.turn_number));
-
-        let building_buttons = self.get_building_buttons(base);
-        building_buttons.set_visible(true);
-
-        let next_food_operator = if state.income_food >= 0 { "+" } else { "-" };
-        let next_metal_operator = if state.income_metal >= 0 { "+" } else { "-" };
-        let next_oxygen_operator = if state.income_oxygen >= 0 { "+" } else { "-" };
-        let next_energy_operator = if state.income_energy >= 0 { "+" } else { "-" };
-
-        let next_food_metal = format!(
-            "{} ({}{})\n{} ({}{})",
-            state.current_food,
-            next_food_operator,
-            state.income_food,
-            state.current_metal,
-            next_metal_operator,
-            state.income_metal
-        );
-        let next_oxygen_energy = format!(
-            "{} ({}{})\n{} ({}{})",
-            state.current_oxygen,
-            next_oxygen_operator,
-            state.income_oxygen,
-            state.current_energy,
-            next_energy_operator,
-            state.income_energy
+    fn show_tile_tooltip(&mut self, #[base] base: &Control, coord: GridCoord) {
+        let text = self.describe_tile(base, coord);
+        let tooltip = Self::get_tile_tooltip(base);
+        let label = unsafe { tooltip.get_node_as::<Label>("Text").unwrap() };
+        label.set_text(text);
+        // Shrink back to fit, since the last tile may have needed more lines.
+        tooltip.set_size(Vector2::ZERO, false);
+        tooltip.set_visible(true);
+        self.tooltip_coord = Some(coord);
+        self.place_tile_tooltip(base);
+    }
+
+    // Moving from one tile to the next can report the new tile before the old one leaves.
+    #[method]
+    fn hide_tile_tooltip(&mut self, #[base] base: &Control, coord: GridCoord) {
+        if self.tooltip_coord == Some(coord) {
+            Self::get_tile_tooltip(base).set_visible(false);
+            self.tooltip_coord = None;
+        }
+    }
+
+    // Keeps the tooltip next to the mouse and inside the window.
+    fn place_tile_tooltip(&self, base: &Control) {
+        let tooltip = Self::get_tile_tooltip(base);
+        let viewport = match base.get_viewport() {
+            Some(viewport) => unsafe { viewport.assume_safe() },
+            None => return,
+        };
+        let window = viewport.get_visible_rect().size;
+        let size = tooltip.size();
+        let mouse = viewport.get_mouse_position() + Vector2::new(16.0, 16.0);
+        tooltip.set_global_position(
+            Vector2::new(
+                mouse.x.min(window.x - size.x).max(0.0),
+                mouse.y.min(window.y - size.y).max(0.0),
+            ),
+            false,
         );
+    }
+
+    #[method]
+    fn _process(&self, #[base] base: &Control, _delta: f64) {
+        if self.tooltip_coord.is_some() {
+            self.place_tile_tooltip(base);
+        }
+    }
 
-        let food_metal = self.get_food_metal_text(base);
-        food_metal.set_text(next_food_metal);
+    // Terrain and deposits from the Map, yields from the same GameManager::compute_yields
+    // that recalculate_income sums.
+    fn describe_tile(&self, base: &Control, coord: GridCoord) -> String {
+        let state = match Self::get_map(base)
+            .map(|m, _| m.visible_state(coord))
+            .unwrap()
+        {
+            Some(state) => state,
+            None => return String::new(),
+        };
+        if !state.explored {
+            return format!("({}, {})\n{}", coord.x, coord.y, tr("Unexplored"));
+        }
+
+        let mut lines = vec![format!(
+            "({}, {}) {}",
+            coord.x,
+            coord.y,
+            tr(state.terrain.name())
+        )];
+        if state.deposit != ResourceType::Nothing {
+            lines.push(tr_args(
+                "{resource} deposit",
+                &[("resource", &tr(state.deposit.name()))],
+            ));
+        }
+        if let Some(building_type) = state.building {
+            let y = unsafe {
+                self.get_game_manager(base)
+                    .call("building_yield", &[coord.to_variant()])
+            };
+            let y = Option::<BuildingYield>::from_variant(&y).unwrap_or(None);
+            let status = match &y {
+                Some(y) if y.active => "{building} (active)",
+                _ => "{building} (inactive)",
+            };
+            lines.push(tr_args(status, &[("building", &tr(building_type.name()))]));
+            if let Some(y) = y {
+                if y.production_type != ResourceType::Nothing {
+                    lines.push(tr_args(
+                        "Produces {amount} {resource}",
+                        &[
+                            ("amount", &y.production),
+                            ("resource", &tr(y.production_type.name())),
+                        ],
+                    ));
+                }
+                if y.upkeep_type != ResourceType::Nothing {
+                    lines.push(tr_args(
+                        "Upkeep {amount} {resource}",
+                        &[
+                            ("amount", &y.upkeep),
+                            ("resource", &tr(y.upkeep_type.name())),
+                        ],
+                    ));
+                }
+                for modifier in y.modifiers.iter() {
+                    lines.push(format!("  {}", tr(modifier.text())));
+                }
+            }
+        }
 
-        let oxygen_energy = self.get_oxygen_energy_text(base);
-        oxygen_energy.set_text(next_oxygen_energy);
+        let delta = unsafe {
+            self.get_game_manager(base)
+                .call("projected_income_delta", &[coord.to_variant()])
+        };
+        if let Ok(Some(delta)) = Option::<ResourceAmounts>::from_variant(&delta) {
+            lines.push(tr("Building here changes income by:"));
+            let changes: Vec<String> = delta
+                .amounts()
+                .iter()
+                .filter(|(_, amount)| *amount != 0)
+                .map(|(resource_type, amount)| {
+                    format!("  {:+} {}", amount, tr(resource_type.name()))
+                })
+                .collect();
+            if changes.is_empty() {
+                lines.push(format!("  {}", tr("Nothing")));
+            }
+            lines.extend(changes);
+        }
+        lines.join("\n")
     }
 
     #[method]
-    fn _on_end_turn_button_pressed(&self, #[base] base: &Control) {
+    fn _on_power_overlay_button_toggled(&self, #[base] base: &Control, pressed: bool) {
+        let game_manager = self.get_game_manager(base);
+        unsafe { game_manager.call("set_power_overlay", &[pressed.to_variant()]) };
+    }
+
+    fn build_debug_menu(&self, base: &Control) {
+        let popup = Self::get_debug_menu(base);
+        popup.add_check_item("", DEBUG_MENU_EDITOR, 0);
+        popup.add_separator("", -1);
+        let tool_count = Self::get_map_editor(base)
+            .map(|e, editor_base| e.tool_labels(&editor_base).len())
+            .unwrap();
+        for id in 0..tool_count as i64 {
+            popup.add_radio_check_item("", id, 0);
+        }
+        popup.set_item_checked(popup.get_item_index(0), true);
+        popup.add_separator("", -1);
+        popup.add_item("", DEBUG_MENU_SAVE_MAP, 0);
+        Self::label_debug_menu(base);
+
+        popup
+            .connect(
+                "id_pressed",
+                unsafe { base.assume_shared() },
+                "_on_debug_menu_id_pressed",
+                VariantArray::new_shared(),
+                0,
+            )
+            .unwrap();
+    }
+
+    // Separate from build_debug_menu so switching language keeps what is checked.
+    fn label_debug_menu(base: &Control) {
+        let popup = Self::get_debug_menu(base);
+        popup.set_item_text(popup.get_item_index(DEBUG_MENU_EDITOR), tr("Map editor"));
+        popup.set_item_text(popup.get_item_index(DEBUG_MENU_SAVE_MAP), tr("Save map"));
+        let tools = Self::get_map_editor(base)
+            .map(|e, editor_base| e.tool_labels(&editor_base))
+            .unwrap();
+        for (id, label) in tools.into_iter().enumerate() {
+            popup.set_item_text(popup.get_item_index(id as i64), label);
+        }
+    }
+
+    #[method]
+    fn _on_debug_menu_id_pressed(&self, #[base] base: &Control, id: i64) {
+        let popup = Self::get_debug_menu(base);
+        let editor = Self::get_map_editor(base);
+        match id {
+            DEBUG_MENU_EDITOR => {
+                let index = popup.get_item_index(id);
+                let active = !popup.is_item_checked(index);
+                popup.set_item_checked(index, active);
+                editor
+                    .map_mut(|e, editor_base| e.set_active(&editor_base, active))
+                    .unwrap();
+                // Closing the editor only brings the buttons back if this turn's action
+                // hasn't been used yet.
+                let can_act = unsafe { self.get_game_manager(base).call("can_act", &[]) };
+                if !active && bool::from_variant(&can_act).unwrap_or(false) {
+                    self.show_building_buttons(base);
+                } else {
+                    self.get_building_buttons(base).set_visible(false);
+                }
+            }
+            DEBUG_MENU_SAVE_MAP => editor.map(|e, editor_base| e.save(&editor_base)).unwrap(),
+            tool => {
+                let tool_count = editor
+                    .map(|e, editor_base| e.tool_labels(&editor_base).len())
+                    .unwrap();
+                for other in 0..tool_count as i64 {
+                    popup.set_item_checked(popup.get_item_index(other), other == tool);
+                }
+                editor
+                    .map_mut(|e, editor_base| e.select_tool(&editor_base, tool))
+                    .unwrap();
+            }
+        }
+    }
+
+    #[method]
+    fn on_end_turn(&self, #[base] base: &Control, state: GameManager) {
+        let turn_text = self.get_turn_text(base);
+        turn_text.set_text(tr_args("Turn: {turn}", &[("turn", &state.turn_number)]));
+
+        // The map editor keeps the buttons hidden until it is closed.
+        let editing = Self::get_map_editor(base)
+            .map(|e, editor_base| e.is_active(&editor_base))
+            .unwrap();
+        if !editing {
+            self.show_building_buttons(base);
+        }
+    }
+
+    fn end_turn(&self, base: &Control) {
         let game_manager = self.get_game_manager(base);
         unsafe { game_manager.call("end_turn", &[]) };
         let state = unsafe { game_manager.call("state", &[]) };
@@ -308,43 +1625,240 @@ impl UI {
         self.on_end_turn(base, state);
     }
 
-    #[method]
-    fn _on_mine_button_pressed(&self, #[base] base: &Control) {
-        let building_buttons = self.get_building_buttons(base);
-        building_buttons.set_visible(false);
+    // The buttons only go away once GameManager has taken the selection.
+    fn select_building(&self, base: &Control, building_type: BuildingType) {
+        let game_manager = self.get_game_manager(base);
+        let accepted =
+            unsafe { game_manager.call("on_select_building", &[building_type.to_variant()]) };
+        if bool::from_variant(&accepted).unwrap_or(false) {
+            self.get_building_buttons(base).set_visible(false);
+        }
+    }
 
+    fn select_scout(&self, base: &Control) {
         let game_manager = self.get_game_manager(base);
-        unsafe { game_manager.call("on_select_building", &[BuildingType::Mine.to_variant()]) };
+        let accepted = unsafe { game_manager.call("on_select_scout", &[]) };
+        if bool::from_variant(&accepted).unwrap_or(false) {
+            self.get_building_buttons(base).set_visible(false);
+        }
     }
 
     #[method]
-    fn _on_greenhouse_button_pressed(&self, #[base] base: &Control) {
-        let building_buttons = self.get_building_buttons(base);
-        building_buttons.set_visible(false);
+    fn _on_end_turn_button_pressed(&self, #[base] base: &Control) {
+        self.end_turn(base);
+    }
 
-        let game_manager = self.get_game_manager(base);
-        unsafe {
-            game_manager.call(
-                "on_select_building",
-                &[BuildingType::Greenhouse.to_variant()],
-            )
+    #[method]
+    fn _on_building_button_pressed(&self, #[base] base: &Control, building_type: BuildingType) {
+        self.select_building(base, building_type);
+    }
+
+    #[method]
+    fn _on_scout_button_pressed(&self, #[base] base: &Control) {
+        self.select_scout(base);
+    }
+
+    // Hotkeys do whatever their button does, and only while the button could be clicked.
+    #[method]
+    fn _unhandled_input(&self, #[base] base: &Control, event: Ref<InputEvent>) {
+        let event = unsafe { event.assume_safe() };
+        let pressed = |action: &str| event.is_action_pressed(action, false, false);
+        let choosing = self.get_building_buttons(base).is_visible();
+
+        let building = self
+            .building_buttons
+            .iter()
+            .find(|(t, _)| pressed(&hotkeys::building_action(t)));
+        if let Some((building_type, _)) = building.filter(|_| choosing) {
+            self.select_building(base, building_type.clone());
+        } else if choosing && pressed(hotkeys::SCOUT) {
+            self.select_scout(base);
+        } else if pressed(hotkeys::END_TURN) {
+            self.end_turn(base);
+        } else if pressed(hotkeys::UNDO) {
+            unsafe { self.get_game_manager(base).call("undo", &[]) };
+        } else {
+            return;
+        }
+        if let Some(viewport) = base.get_viewport() {
+            unsafe { viewport.assume_safe() }.set_input_as_handled();
+        }
+    }
+
+    fn build_language_button(&self, base: &Control) {
+        let button = unsafe { base.get_node_as::<OptionButton>("LanguageButton").unwrap() };
+        for (index, (_, name)) in i18n::LANGUAGES.iter().enumerate() {
+            button.add_item(*name, index as i64);
+        }
+        button.select(i18n::current_language() as i64);
+    }
+
+    #[method]
+    fn _on_language_selected(&mut self, #[base] base: &Control, index: i64) {
+        i18n::set_language(index as usize);
+        self.retranslate(base);
+    }
+
+    fn free_children(node: TRef<'static, impl SubClass<Node>>) {
+        let node = node.upcast::<Node>();
+        for child in node.get_children().iter() {
+            if let Some(child) = child.to_object::<Node>() {
+                let child = unsafe { child.assume_safe() };
+                node.remove_child(child);
+                child.queue_free();
+            }
+        }
+    }
+
+    // Godot translates the text the scene sets by itself, so this only redoes what is
+    // built here. Event log entries stay in the language they were logged in.
+    fn retranslate(&mut self, base: &Control) {
+        let row = self.get_building_buttons(base);
+        for (_, button) in self.building_buttons.drain(..) {
+            let button = unsafe { button.assume_safe() };
+            row.remove_child(button);
+            button.queue_free();
+        }
+        self.build_building_buttons(base);
+
+        Self::free_children(unsafe { base.get_node_as::<GridContainer>("ResourcePanel").unwrap() });
+        self.resource_rows.clear();
+        self.build_resource_panel(base);
+        // Also refreshes the building buttons and any open panel.
+        self.update_resources(base);
+
+        // Relabelled rather than rebuilt, so hidden resources stay hidden.
+        let toggles = unsafe {
+            base.get_node_as::<HBoxContainer>("HistoryPanel/Content/Toggles")
+                .unwrap()
+        };
+        for (toggle, resource_type) in toggles.get_children().iter().zip(ResourceType::STORED) {
+            if let Some(toggle) = toggle.to_object::<CheckBox>() {
+                unsafe { toggle.assume_safe() }.set_text(tr(resource_type.name()));
+            }
+        }
+        Self::get_history_graph(base).base().update();
+
+        self.rebinding = None;
+        Self::free_children(unsafe {
+            base.get_node_as::<GridContainer>("KeysPanel/Content/Bindings")
+                .unwrap()
+        });
+        self.key_buttons.clear();
+        self.build_keys_panel(base);
+
+        Self::label_debug_menu(base);
+        let turn = i32::from_variant(&self.get_game_manager(base).get("turn_number")).unwrap_or(1);
+        self.get_turn_text(base)
+            .set_text(tr_args("Turn: {turn}", &[("turn", &turn)]));
+        if let Some(coord) = self.tooltip_coord {
+            self.show_tile_tooltip(base, coord);
+        }
+    }
+
+    fn get_keys_panel(base: &Control) -> TRef<'static, Control> {
+        unsafe { base.get_node_as::<Control>("KeysPanel").unwrap() }
+    }
+
+    // A row per hotkey: what it does, and a button showing its keys that remaps it.
+    fn build_keys_panel(&mut self, base: &Control) {
+        let grid = unsafe {
+            base.get_node_as::<GridContainer>("KeysPanel/Content/Bindings")
+                .unwrap()
         };
+        grid.set_columns(2);
+        for hotkey in hotkeys::all() {
+            let label = Label::new();
+            label.set_text(hotkey.label.as_str());
+            grid.add_child(label, false);
+
+            let button = Button::new();
+            button.set_h_size_flags(Control::SIZE_EXPAND_FILL);
+            button
+                .connect(
+                    "pressed",
+                    unsafe { base.assume_shared() },
+                    "_on_rebind_pressed",
+                    VariantArray::from_iter([hotkey.action.to_variant()]).into_shared(),
+                    0,
+                )
+                .unwrap();
+            let button = button.into_shared();
+            grid.add_child(button.clone(), false);
+            self.key_buttons.push((hotkey.action, button));
+        }
+        self.refresh_key_buttons();
+    }
+
+    fn refresh_key_buttons(&self) {
+        for (action, button) in self.key_buttons.iter() {
+            let text = if self.rebinding.as_ref() == Some(action) {
+                tr("Press a key... (click to keep)")
+            } else {
+                hotkeys::key_text(action)
+            };
+            unsafe { button.assume_safe() }.set_text(text);
+        }
     }
 
     #[method]
-    fn _on_solar_panel_button_pressed(&self, #[base] base: &Control) {
-        godot_print!("In solar panel button press");
-        let building_buttons = self.get_building_buttons(base);
-        godot_print!("Have building buttons");
-        building_buttons.set_visible(false);
+    fn _on_keys_button_toggled(&mut self, #[base] base: &Control, pressed: bool) {
+        self.rebinding = None;
+        self.refresh_key_buttons();
+        Self::get_keys_panel(base).set_visible(pressed);
+    }
 
-        let game_manager = self.get_game_manager(base);
-        unsafe {
-            game_manager.call(
-                "on_select_building",
-                &[BuildingType::SolarPanel.to_variant()],
-            )
+    // A second click on the waiting button keeps the keys it had, so any key, Escape
+    // included, can be bound.
+    #[method]
+    fn _on_rebind_pressed(&mut self, #[base] _base: &Control, action: String) {
+        if self.rebinding.as_ref() == Some(&action) {
+            self.rebinding = None;
+        } else {
+            self.rebinding = Some(action);
+        }
+        self.refresh_key_buttons();
+    }
+
+    #[method]
+    fn _on_reset_keys_pressed(&mut self, #[base] _base: &Control) {
+        self.rebinding = None;
+        hotkeys::reset();
+        self.refresh_key_buttons();
+    }
+
+    // Sees keys before anything else while an action is waiting for its new key.
+    #[method]
+    fn _input(&mut self, #[base] base: &Control, event: Ref<InputEvent>) {
+        let action = match &self.rebinding {
+            Some(action) => action.clone(),
+            None => return,
         };
+        let event = unsafe { event.assume_safe() };
+        let key = match event.cast::<InputEventKey>() {
+            Some(key) if key.is_pressed() && !key.is_echo() => key,
+            _ => return,
+        };
+        // Modifiers on their own are only the start of a combination like Control+Z.
+        let scancode = key.scancode();
+        if [
+            GlobalConstants::KEY_CONTROL,
+            GlobalConstants::KEY_SHIFT,
+            GlobalConstants::KEY_ALT,
+            GlobalConstants::KEY_META,
+        ]
+        .contains(&scancode)
+        {
+            return;
+        }
+
+        hotkeys::bind(&action, &[key.get_scancode_with_modifiers()]);
+        hotkeys::save();
+        self.rebinding = None;
+        self.refresh_key_buttons();
+        if let Some(viewport) = base.get_viewport() {
+            unsafe { viewport.assume_safe() }.set_input_as_handled();
+        }
     }
 }
 
@@ -354,15 +1868,56 @@ pub enum BuildingType {
     Mine = 1,
     Greenhouse = 2,
     SolarPanel = 3,
+    // Produces nothing, only links buildings to the Base.
+    Conduit = 4,
 }
 
 impl BuildingType {
+    pub const ALL: [BuildingType; 5] = [
+        BuildingType::Base,
+        BuildingType::Mine,
+        BuildingType::Greenhouse,
+        BuildingType::SolarPanel,
+        BuildingType::Conduit,
+    ];
+
+    pub fn from_index(index: i64) -> Option<Self> {
+        Self::ALL.get(usize::try_from(index).ok()?).cloned()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuildingType::Base => "Base",
+            BuildingType::Mine => "Mine",
+            BuildingType::Greenhouse => "Greenhouse",
+            BuildingType::SolarPanel => "Solar Panel",
+            BuildingType::Conduit => "Conduit",
+        }
+    }
+
+    // One line for the build buttons' tooltips. Like name, this is the English catalogue key.
+    pub fn description(&self) -> &'static str {
+        match self {
+            BuildingType::Base => "The heart of the colony. Everything has to link back to it.",
+            BuildingType::Mine => "Digs metal out of the ground.",
+            BuildingType::Greenhouse => "Grows food for the colonists.",
+            BuildingType::SolarPanel => "Turns sunlight into energy.",
+            BuildingType::Conduit => "Links buildings to the Base.",
+        }
+    }
+
+    // The Base comes with the map and can't be built.
+    pub fn is_placeable(&self) -> bool {
+        *self != BuildingType::Base
+    }
+
     pub fn get_texture_path(&self) -> String {
         match self {
             BuildingType::Base => "res://Sprites/Base.png".to_string(),
             BuildingType::Mine => "res://Sprites/Mine.png".to_string(),
             BuildingType::Greenhouse => "res://Sprites/Greenhouse.png".to_string(),
             BuildingType::SolarPanel => "res://Sprites/SolarPanel.png".to_string(),
+            BuildingType::Conduit => "res://Sprites/Conduit.png".to_string(),
         }
     }
 }
@@ -374,6 +1929,7 @@ impl ToVariant for BuildingType {
             BuildingType::Mine => 1.to_variant(),
             BuildingType::Greenhouse => 2.to_variant(),
             BuildingType::SolarPanel => 3.to_variant(),
+            BuildingType::Conduit => 4.to_variant(),
         }
     }
 }
@@ -386,9 +1942,10 @@ impl FromVariant for BuildingType {
             1 => Ok(BuildingType::Mine),
             2 => Ok(BuildingType::Greenhouse),
             3 => Ok(BuildingType::SolarPanel),
+            4 => Ok(BuildingType::Conduit),
             _ => Err(FromVariantError::UnknownEnumVariant {
                 variant: "i64".to_owned(),
-                expected: &["0", "1", "2", "3"],
+                expected: &["0", "1", "2", "3", "4"],
             }),
         }
     }
@@ -403,6 +1960,7 @@ impl Export for BuildingType {
             "Mine".to_owned(),
             "Greenhouse".to_owned(),
             "SolarPanel".to_owned(),
+            "Conduit".to_owned(),
         ]))
         .export_info()
     }
@@ -461,6 +2019,157 @@ impl Export for ResourceType {
     }
 }
 
+impl ResourceType {
+    // Everything that is stockpiled, in the order the UI lists it.
+    pub const STORED: [ResourceType; 4] = [
+        ResourceType::Food,
+        ResourceType::Metal,
+        ResourceType::Oxygen,
+        ResourceType::Energy,
+    ];
+
+    // Where this resource sits in STORED, and so in ResourceAmounts.
+    pub fn stored_index(&self) -> Option<usize> {
+        ResourceType::STORED.iter().position(|t| t == self)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResourceType::Nothing => "Nothing",
+            ResourceType::Food => "Food",
+            ResourceType::Metal => "Metal",
+            ResourceType::Oxygen => "Oxygen",
+            ResourceType::Energy => "Energy",
+        }
+    }
+
+    pub fn from_index(index: i64) -> Option<Self> {
+        match index {
+            0 => Some(ResourceType::Nothing),
+            1 => Some(ResourceType::Food),
+            2 => Some(ResourceType::Metal),
+            3 => Some(ResourceType::Oxygen),
+            4 => Some(ResourceType::Energy),
+            _ => None,
+        }
+    }
+
+    pub fn get_color(&self) -> Color {
+        match self {
+            ResourceType::Nothing => Color::from_rgb(1.0, 1.0, 1.0),
+            ResourceType::Food => Color::from_rgb(0.35, 0.8, 0.3),
+            ResourceType::Metal => Color::from_rgb(0.6, 0.7, 0.85),
+            ResourceType::Oxygen => Color::from_rgb(0.55, 0.85, 1.0),
+            ResourceType::Energy => Color::from_rgb(1.0, 0.85, 0.2),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Terrain {
+    Plains = 0,
+    Dunes = 1,
+    Rock = 2,
+    Crater = 3,
+}
+
+impl Terrain {
+    pub const ALL: [Terrain; 4] = [
+        Terrain::Plains,
+        Terrain::Dunes,
+        Terrain::Rock,
+        Terrain::Crater,
+    ];
+
+    pub fn from_index(index: i64) -> Option<Self> {
+        Self::ALL.get(usize::try_from(index).ok()?).cloned()
+    }
+
+    // The terrain whose display colour is nearest, for maps painted as images.
+    pub fn closest_to_color(color: Color) -> Self {
+        let distance = |terrain: &Terrain| {
+            let c = terrain.get_color();
+            (c.r - color.r).powi(2) + (c.g - color.g).powi(2) + (c.b - color.b).powi(2)
+        };
+        Self::ALL
+            .iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .cloned()
+            .unwrap()
+    }
+
+    pub fn from_elevation(elevation: f32) -> Self {
+        if elevation < 0.3 {
+            Terrain::Crater
+        } else if elevation < 0.55 {
+            Terrain::Plains
+        } else if elevation < 0.7 {
+            Terrain::Dunes
+        } else {
+            Terrain::Rock
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Terrain::Plains => "Plains",
+            Terrain::Dunes => "Dunes",
+            Terrain::Rock => "Rock",
+            Terrain::Crater => "Crater",
+        }
+    }
+
+    pub fn get_color(&self) -> Color {
+        match self {
+            Terrain::Plains => Color::from_rgb(1.0, 1.0, 1.0),
+            Terrain::Dunes => Color::from_rgb(1.0, 0.88, 0.7),
+            Terrain::Rock => Color::from_rgb(0.72, 0.66, 0.62),
+            Terrain::Crater => Color::from_rgb(0.35, 0.3, 0.3),
+        }
+    }
+}
+
+impl ToVariant for Terrain {
+    fn to_variant(&self) -> Variant {
+        match self {
+            Terrain::Plains => 0.to_variant(),
+            Terrain::Dunes => 1.to_variant(),
+            Terrain::Rock => 2.to_variant(),
+            Terrain::Crater => 3.to_variant(),
+        }
+    }
+}
+
+impl FromVariant for Terrain {
+    fn from_variant(variant: &Variant) -> Result<Self, FromVariantError> {
+        let result = i64::from_variant(variant)?;
+        match result {
+            0 => Ok(Terrain::Plains),
+            1 => Ok(Terrain::Dunes),
+            2 => Ok(Terrain::Rock),
+            3 => Ok(Terrain::Crater),
+            _ => Err(FromVariantError::UnknownEnumVariant {
+                variant: "i64".to_owned(),
+                expected: &["0", "1", "2", "3"],
+            }),
+        }
+    }
+}
+
+impl Export for Terrain {
+    type Hint = IntHint<u32>;
+
+    fn export_info(_hint: Option<Self::Hint>) -> ExportInfo {
+        Self::Hint::Enum(EnumHint::new(vec![
+            "Plains".to_owned(),
+            "Dunes".to_owned(),
+            "Rock".to_owned(),
+            "Crater".to_owned(),
+        ]))
+        .export_info()
+    }
+}
+
 #[derive(NativeClass, ToVariant, FromVariant)]
 #[inherit(Node)]
 pub struct Building {
@@ -527,6 +2236,109 @@ impl Building {
             upkeep_type: ResourceType::Nothing,
         }
     }
+
+    fn conduit() -> Self {
+        Building {
+            building_type: BuildingType::Conduit,
+            resource_amount: 0,
+            resource_type: ResourceType::Nothing,
+            upkeep_amount: 0,
+            upkeep_type: ResourceType::Nothing,
+        }
+    }
+}
+
+// One amount per stored resource, in ResourceType::STORED order: a stockpile, a turn's
+// income, or how much either would change.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct ResourceAmounts([i32; ResourceType::STORED.len()]);
+
+impl ResourceAmounts {
+    pub fn add(&mut self, resource_type: &ResourceType, amount: i32) {
+        if let Some(value) = self.get_mut(resource_type) {
+            *value += amount;
+        }
+    }
+
+    pub fn get(&self, resource_type: &ResourceType) -> i32 {
+        resource_type.stored_index().map(|i| self.0[i]).unwrap_or(0)
+    }
+
+    // None for resources that aren't stored.
+    pub fn get_mut(&mut self, resource_type: &ResourceType) -> Option<&mut i32> {
+        resource_type.stored_index().map(move |i| &mut self.0[i])
+    }
+
+    // Every stored resource with its amount, in the order the UI lists them.
+    pub fn amounts(&self) -> Vec<(ResourceType, i32)> {
+        ResourceType::STORED
+            .iter()
+            .map(|t| (t.clone(), self.get(t)))
+            .collect()
+    }
+}
+
+// One row of the resource panel.
+#[derive(Clone, ToVariant, FromVariant)]
+pub struct ResourceStatus {
+    pub resource_type: ResourceType,
+    pub amount: i32,
+    pub capacity: i32,
+    pub income: i32,
+}
+
+// Stockpiles and income as a turn ended, for the history graphs.
+#[derive(Clone, ToVariant, FromVariant)]
+pub struct TurnSnapshot {
+    pub turn: i32,
+    pub stock: ResourceAmounts,
+    pub income: ResourceAmounts,
+}
+
+// One contribution to a resource's income: a building's production or upkeep, or a power
+// grid's spare energy. The entries for a resource add up to its income.
+#[derive(Clone, ToVariant, FromVariant)]
+pub struct IncomeEntry {
+    pub resource_type: ResourceType,
+    pub source: String,
+    // Why the amount is what it is, empty when it is just the BuildingData numbers.
+    pub detail: String,
+    pub amount: i32,
+    // The tile to look at for it.
+    pub coord: Option<GridCoord>,
+}
+
+impl std::ops::Sub for ResourceAmounts {
+    type Output = ResourceAmounts;
+
+    fn sub(self, other: ResourceAmounts) -> ResourceAmounts {
+        let mut result = self;
+        for (value, other) in result.0.iter_mut().zip(other.0) {
+            *value -= other;
+        }
+        result
+    }
+}
+
+// Passed around as an array of the amounts, in STORED order.
+impl ToVariant for ResourceAmounts {
+    fn to_variant(&self) -> Variant {
+        self.0.to_vec().to_variant()
+    }
+}
+
+impl FromVariant for ResourceAmounts {
+    fn from_variant(variant: &Variant) -> Result<Self, FromVariantError> {
+        let amounts = Vec::<i32>::from_variant(variant)?;
+        let len = amounts.len();
+        let amounts = amounts
+            .try_into()
+            .map_err(|_| FromVariantError::InvalidLength {
+                len,
+                expected: ResourceType::STORED.len(),
+            })?;
+        Ok(ResourceAmounts(amounts))
+    }
 }
 
 #[derive(NativeClass)]
@@ -559,52 +2371,112 @@ impl BuildingData {
             BuildingType::Mine => Building::mine(),
             BuildingType::Greenhouse => Building::greenhouse(),
             BuildingType::SolarPanel => Building::solar_panel(),
+            BuildingType::Conduit => Building::conduit(),
         }
     }
+
+    // Everything the player can build, in BuildingType order.
+    #[method]
+    fn placeable(&self, #[base] base: &Node) -> Vec<Building> {
+        BuildingType::ALL
+            .iter()
+            .filter(|t| t.is_placeable())
+            .map(|t| self.data(base, t.clone()))
+            .collect()
+    }
 }
 
 #[derive(NativeClass, Clone, ToVariant, FromVariant)]
 #[inherit(Node2D)]
+#[register_with(Self::register)]
 pub struct GameManager {
-    #[property(default = 0)]
-    current_food: i32,
-    #[property(default = 0)]
-    current_metal: i32,
-    #[property(default = 0)]
-    current_oxygen: i32,
-    #[property(default = 0)]
-    current_energy: i32,
-    #[property(default = 0)]
-    income_food: i32,
-    #[property(default = 0)]
-    income_metal: i32,
-    #[property(default = 0)]
-    income_oxygen: i32,
-    #[property(default = 0)]
-    income_energy: i32,
+    // What is stockpiled of each resource, and what the next end_turn adds to it. Godot
+    // sees them as current_food, income_food and so on, see register.
+    stock: ResourceAmounts,
+    income: ResourceAmounts,
     #[property(default = 1)]
     turn_number: i32,
     #[property(default = false)]
     is_placing_building: bool,
+    #[property(default = false)]
+    is_scouting: bool,
     #[property]
     building_to_place: BuildingType,
+    // Shown next to each stockpile in the resource panel.
+    #[property(default = 100)]
+    storage_capacity: i32,
+    // Share energy through power grids instead of one global pool.
+    #[property(default = false)]
+    power_grid: bool,
+    power_overlay: bool,
+    // Whether this turn's one action, a building or a scout, is used up.
+    has_acted: bool,
+    // The building placed this turn, which undo can still take back.
+    last_placement: Option<GridCoord>,
+    // One snapshot per finished turn, oldest first.
+    history: Vec<TurnSnapshot>,
+    event_log: Vec<LogEntry>,
+    // compute_yields as of the last recalculate_income.
+    #[variant(skip)]
+    yields: Vec<BuildingYield>,
+    #[variant(skip)]
+    power_grids: Vec<PowerGrid>,
+    // The last projected_income_delta, kept until the tile, the building or the map changes.
+    #[variant(skip)]
+    projection: Option<(GridCoord, BuildingType, Option<ResourceAmounts>)>,
 }
 
 #[methods]
 impl GameManager {
     fn new(_base: &Node2D) -> Self {
         GameManager {
-            current_food: 0,
-            current_metal: 0,
-            current_oxygen: 0,
-            current_energy: 0,
-            income_food: 0,
-            income_metal: 0,
-            income_oxygen: 0,
-            income_energy: 0,
+            stock: ResourceAmounts::default(),
+            income: ResourceAmounts::default(),
             turn_number: 1,
             is_placing_building: false,
+            is_scouting: false,
             building_to_place: BuildingType::Base,
+            storage_capacity: 100,
+            power_grid: false,
+            power_overlay: false,
+            has_acted: false,
+            last_placement: None,
+            history: Vec::new(),
+            event_log: Vec::new(),
+            yields: Vec::new(),
+            power_grids: Vec::new(),
+            projection: None,
+        }
+    }
+
+    // current_food, income_food and so on for every stored resource, backed by the arrays.
+    fn register(builder: &ClassBuilder<Self>) {
+        type Field = (
+            &'static str,
+            fn(&GameManager) -> &ResourceAmounts,
+            fn(&mut GameManager) -> &mut ResourceAmounts,
+        );
+        let fields: [Field; 2] = [
+            ("current", |g| &g.stock, |g| &mut g.stock),
+            ("income", |g| &g.income, |g| &mut g.income),
+        ];
+        for (prefix, get, get_mut) in fields {
+            for resource_type in ResourceType::STORED {
+                let name = format!("{}_{}", prefix, resource_type.name().to_lowercase());
+                let set_type = resource_type.clone();
+                builder
+                    .property::<i32>(&name)
+                    .with_default(0)
+                    .with_getter(move |this: &Self, _base: TRef<Node2D>| {
+                        get(this).get(&resource_type)
+                    })
+                    .with_setter(move |this: &mut Self, _base: TRef<Node2D>, value: i32| {
+                        if let Some(amount) = get_mut(this).get_mut(&set_type) {
+                            *amount = value;
+                        }
+                    })
+                    .done();
+            }
         }
     }
 
@@ -614,19 +2486,158 @@ impl GameManager {
     }
 
     #[method]
-    fn _ready(&self, #[base] base: &Node2D) {
+    fn _ready(&mut self, #[base] base: &Node2D) {
         godot_print!("Hello from Game Manager!");
-        let ui = unsafe { base.get_node_as::<Control>("UI").unwrap() };
-        unsafe { ui.call("update_resource_text", &[]) };
-        unsafe { ui.call("on_end_turn", &[]) };
+        // Loaded maps can come with buildings already on them.
+        self.recalculate_income(base);
+        let ui = unsafe { base.get_node_as::<Control>("HUD/UI").unwrap() };
+        unsafe { ui.call_deferred("on_end_turn", &[self.to_variant()]) };
+    }
+
+    // Deferred, since the UI reads the new numbers back from this node.
+    fn resources_changed(&self, base: &Node2D) {
+        let ui = unsafe { base.get_node_as::<Control>("HUD/UI").unwrap() };
+        unsafe { ui.call_deferred("update_resources", &[]) };
+    }
+
+    #[method]
+    fn history(&self, #[base] _base: &Node2D) -> Vec<TurnSnapshot> {
+        self.history.clone()
+    }
+
+    // Adds to the event log and shows it in the UI.
+    fn log(&mut self, base: &Node2D, kind: LogKind, text: String) {
+        let entry = LogEntry {
+            turn: self.turn_number,
+            kind,
+            text,
+        };
+        godot_print!("{}", entry.line());
+        let ui = unsafe { base.get_node_as::<Control>("HUD/UI").unwrap() };
+        unsafe { ui.call_deferred("on_log_entry", &[entry.to_variant()]) };
+        self.event_log.push(entry);
+    }
+
+    #[method]
+    fn event_log(&self, #[base] _base: &Node2D) -> Vec<LogEntry> {
+        self.event_log.clone()
+    }
+
+    // For restoring a game, like set_history.
+    #[method]
+    fn set_event_log(&mut self, #[base] base: &Node2D, event_log: Vec<LogEntry>) {
+        self.event_log = event_log;
+        let ui = unsafe { base.get_node_as::<Control>("HUD/UI").unwrap() };
+        unsafe { ui.call_deferred("rebuild_event_log", &[]) };
+    }
+
+    // For restoring a game: replaces everything recorded so far.
+    #[method]
+    fn set_history(&mut self, #[base] base: &Node2D, history: Vec<TurnSnapshot>) {
+        self.history = history;
+        self.resources_changed(base);
+    }
+
+    #[method]
+    fn resources(&self, #[base] _base: &Node2D) -> Vec<ResourceStatus> {
+        ResourceType::STORED
+            .iter()
+            .map(|t| ResourceStatus {
+                resource_type: t.clone(),
+                amount: self.stock.get(t),
+                capacity: self.storage_capacity,
+                income: self.income.get(t),
+            })
+            .collect()
+    }
+
+    // Adds to (or takes from) a stockpile.
+    fn change_stock(&mut self, resource_type: &ResourceType, amount: i32) {
+        self.stock.add(resource_type, amount);
+    }
+
+    #[method]
+    fn can_act(&self, #[base] _base: &Node2D) -> bool {
+        !self.has_acted
     }
 
+    // Whether placing the building has started; false once this turn's action is used.
     #[method]
-    fn on_select_building(&mut self, #[base] base: &Node2D, building_type: BuildingType) {
+    fn on_select_building(&mut self, #[base] base: &Node2D, building_type: BuildingType) -> bool {
+        if self.has_acted || !building_type.is_placeable() {
+            return false;
+        }
         self.is_placing_building = true;
         self.building_to_place = building_type;
         let map = unsafe { base.get_node_as::<Node>("Tiles").unwrap() };
         unsafe { map.call("highlight_available_tiles", &[]) };
+        true
+    }
+
+    // Scouting takes the turn's action just like building does.
+    #[method]
+    fn on_select_scout(&mut self, #[base] base: &Node2D) -> bool {
+        if self.has_acted {
+            return false;
+        }
+        self.is_scouting = true;
+        Self::get_map(base)
+            .map_mut(|m, map_base| m.highlight_scout_targets(&map_base))
+            .unwrap();
+        true
+    }
+
+    // Backs out of choosing where to build or scout, without using up the turn's action.
+    #[method]
+    fn cancel_selection(&mut self, #[base] base: &Node2D) {
+        if !self.is_placing_building && !self.is_scouting {
+            return;
+        }
+        self.is_placing_building = false;
+        self.is_scouting = false;
+        Self::get_map(base)
+            .map_mut(|m, map_base| m.disable_tile_highlights(&map_base))
+            .unwrap();
+        let ui = unsafe { base.get_node_as::<Control>("HUD/UI").unwrap() };
+        unsafe { ui.call("show_building_buttons", &[]) };
+    }
+
+    // Escape or right click, unless the UI used it first.
+    #[method]
+    fn _unhandled_input(&mut self, #[base] base: &Node2D, event: Ref<InputEvent>) {
+        let event = unsafe { event.assume_safe() };
+        if !event.is_action_pressed("cancel", false, false) {
+            return;
+        }
+        if self.is_placing_building || self.is_scouting {
+            self.cancel_selection(base);
+            if let Some(viewport) = base.get_viewport() {
+                unsafe { viewport.assume_safe() }.set_input_as_handled();
+            }
+        }
+    }
+
+    #[method]
+    fn scout(&mut self, #[base] base: &Node2D, coord: GridCoord) {
+        let map = Self::get_map(base);
+        if !map
+            .map(|m, map_base| m.can_scout(&map_base, coord))
+            .unwrap()
+        {
+            return;
+        }
+        self.is_scouting = false;
+        self.has_acted = true;
+        map.map_mut(|m, map_base| m.scout(&map_base, coord))
+            .unwrap();
+        self.log(
+            base,
+            LogKind::Event,
+            tr_args(
+                "Scouted around ({x}, {y})",
+                &[("x", &coord.x), ("y", &coord.y)],
+            ),
+        );
     }
 
     #[method]
@@ -636,34 +2647,365 @@ impl GameManager {
         resource_type: ResourceType,
         amount: i32,
     ) {
-        match resource_type {
-            ResourceType::Energy => self.income_energy += amount,
-            ResourceType::Food => self.income_food += amount,
-            ResourceType::Metal => self.income_metal += amount,
-            ResourceType::Oxygen => self.income_oxygen += amount,
-            ResourceType::Nothing => {}
+        self.income.add(&resource_type, amount);
+    }
+
+    fn get_map(base: &Node2D) -> TInstance<'static, Map> {
+        unsafe { base.get_node_as_instance::<Map>("Tiles").unwrap() }
+    }
+
+    #[method]
+    fn on_tile_clicked(&mut self, #[base] base: &Node2D, coord: GridCoord) {
+        let editor = unsafe { base.get_node_as_instance::<MapEditor>("MapEditor").unwrap() };
+        if editor
+            .map(|e, editor_base| e.is_active(&editor_base))
+            .unwrap()
+        {
+            editor
+                .map(|e, editor_base| e.edit_tile(&editor_base, coord))
+                .unwrap();
+        } else if self.is_placing_building {
+            self.place_building(base, coord);
+        } else if self.is_scouting {
+            self.scout(base, coord);
+        }
+    }
+
+    #[method]
+    fn place_building(&mut self, #[base] base: &Node2D, coord: GridCoord) {
+        let map = Self::get_map(base);
+        if !map
+            .map(|m, map_base| m.can_place_building(&map_base, coord))
+            .unwrap()
+        {
+            return;
+        }
+
+        self.is_placing_building = false;
+        let building_type = self.building_to_place.clone();
+        self.has_acted = true;
+        self.last_placement = Some(coord);
+        map.map_mut(|m, map_base| m.place_building(&map_base, coord, building_type.clone()))
+            .unwrap();
+        self.recalculate_income(base);
+
+        self.log(
+            base,
+            LogKind::Building,
+            tr_args(
+                "Built a {building} at ({x}, {y})",
+                &[
+                    ("building", &tr(building_type.name())),
+                    ("x", &coord.x),
+                    ("y", &coord.y),
+                ],
+            ),
+        );
+        let (count, of_type) = map
+            .map(|m, _| {
+                let built: Vec<&BuildingType> = m
+                    .grid()
+                    .buildings()
+                    .map(|(_, b)| b)
+                    .filter(|b| b.is_placeable())
+                    .collect();
+                let of_type = built.iter().filter(|b| ***b == building_type).count();
+                (built.len(), of_type)
+            })
+            .unwrap();
+        if of_type == 1 {
+            self.log(
+                base,
+                LogKind::Milestone,
+                tr_args(
+                    "First {building} built",
+                    &[("building", &tr(building_type.name()))],
+                ),
+            );
+        }
+        if event_log::BUILDING_MILESTONES.contains(&count) {
+            self.log(
+                base,
+                LogKind::Milestone,
+                tr_args("The colony has {count} buildings", &[("count", &count)]),
+            );
         }
     }
 
+    // Takes back this turn's building, so another action can be chosen.
+    // Whatever it explored stays explored.
     #[method]
-    fn place_building(&mut self, #[base] base: &Node2D, tile_position: Vector2) {
+    fn undo(&mut self, #[base] base: &Node2D) {
+        if self.is_placing_building || self.is_scouting {
+            return;
+        }
+        let coord = match self.last_placement.take() {
+            Some(coord) => coord,
+            None => return,
+        };
+        let map = Self::get_map(base);
+        let building_type = map
+            .map(|m, _| m.grid().get(coord).and_then(|t| t.building.clone()))
+            .unwrap();
+        let building_type = match building_type {
+            Some(building_type) => building_type,
+            None => return,
+        };
+
+        map.map_mut(|m, map_base| m.remove_building(&map_base, coord))
+            .unwrap();
+        self.has_acted = false;
+        self.recalculate_income(base);
+        self.log(
+            base,
+            LogKind::Building,
+            tr_args(
+                "Took back the {building} at ({x}, {y})",
+                &[
+                    ("building", &tr(building_type.name())),
+                    ("x", &coord.x),
+                    ("y", &coord.y),
+                ],
+            ),
+        );
+        let ui = unsafe { base.get_node_as::<Control>("HUD/UI").unwrap() };
+        unsafe { ui.call_deferred("show_building_buttons", &[]) };
+    }
+
+    fn building_data(base: &Node2D, building_type: &BuildingType) -> Building {
         let b = unsafe {
-            BuildingData::get_singleton_node(base)
-                .call("data", &[self.building_to_place.to_variant()])
+            BuildingData::get_singleton_node(base).call("data", &[building_type.to_variant()])
         };
+        Building::from_variant(&b).unwrap()
+    }
+
+    // What every building on the map makes and costs this turn, plus each power grid.
+    // recalculate_income only sums these, so anything showing them agrees with end_turn.
+    fn compute_yields(&self, base: &Node2D) -> (Vec<BuildingYield>, Vec<PowerGrid>) {
+        Self::get_map(base)
+            .map(|m, _| {
+                yields::compute(
+                    m.grid(),
+                    m.layout(),
+                    self.power_grid,
+                    &|t: &BuildingType| Self::building_data(base, t),
+                )
+            })
+            .unwrap()
+    }
+
+    #[method]
+    fn building_yields(&self, #[base] _base: &Node2D) -> Vec<BuildingYield> {
+        self.yields.clone()
+    }
+
+    #[method]
+    fn building_yield(&self, #[base] _base: &Node2D, coord: GridCoord) -> Option<BuildingYield> {
+        self.yields.iter().find(|y| y.coord == coord).cloned()
+    }
+
+    // Inactive buildings are listed too, at 0, since they are usually what is wrong.
+    fn income_entries(yields: &[BuildingYield], grids: &[PowerGrid]) -> Vec<IncomeEntry> {
+        let mut entries = Vec::new();
+        for y in yields.iter() {
+            let source = format!(
+                "{} ({}, {})",
+                tr(y.building_type.name()),
+                y.coord.x,
+                y.coord.y
+            );
+            let detail = y
+                .modifiers
+                .iter()
+                .map(|m| tr(m.text()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            for (resource_type, amount) in y.amounts() {
+                if resource_type == ResourceType::Nothing {
+                    continue;
+                }
+                entries.push(IncomeEntry {
+                    resource_type,
+                    source: source.clone(),
+                    detail: detail.clone(),
+                    amount,
+                    coord: Some(y.coord),
+                });
+            }
+        }
+        // Whatever whole units each grid has spare are stored.
+        for (index, g) in grids.iter().enumerate() {
+            entries.push(IncomeEntry {
+                resource_type: ResourceType::Energy,
+                source: tr_args("Power grid {number} spare", &[("number", &(index + 1))]),
+                detail: tr_args(
+                    "{supplied} supplied, {used} used",
+                    &[
+                        ("supplied", &format!("{:.1}", g.supply)),
+                        ("used", &format!("{:.1}", g.demand)),
+                    ],
+                ),
+                amount: g.spare.floor() as i32,
+                coord: g.tiles.first().cloned(),
+            });
+        }
+        entries
+    }
+
+    #[method]
+    fn income_breakdown(&self, #[base] _base: &Node2D) -> Vec<IncomeEntry> {
+        Self::income_entries(&self.yields, &self.power_grids)
+    }
+
+    // How income_* would change if building_to_place went on this tile, or None when it
+    // can't go there. Kept for the tile until something changes, since the tooltip asks
+    // again every time it is rebuilt.
+    #[method]
+    fn projected_income_delta(
+        &mut self,
+        #[base] base: &Node2D,
+        coord: GridCoord,
+    ) -> Option<ResourceAmounts> {
+        if !self.is_placing_building {
+            return None;
+        }
+        if let Some((cached_coord, building_type, delta)) = &self.projection {
+            if *cached_coord == coord && *building_type == self.building_to_place {
+                return *delta;
+            }
+        }
+        let delta = Self::get_map(base)
+            .map(|m, map_base| {
+                if !m.can_place_building(&map_base, coord) {
+                    return None;
+                }
+                Some(yields::placement_delta(
+                    m.grid(),
+                    m.layout(),
+                    self.power_grid,
+                    coord,
+                    &self.building_to_place,
+                    self.income,
+                    &|t: &BuildingType| Self::building_data(base, t),
+                ))
+            })
+            .unwrap();
+        self.projection = Some((coord, self.building_to_place.clone(), delta));
+        delta
+    }
+
+    #[method]
+    fn on_tile_hovered(&mut self, #[base] base: &Node2D, coord: GridCoord) {
+        if !self.is_placing_building {
+            return;
+        }
+        let building_type = self.building_to_place.clone();
+        Self::get_map(base)
+            .map_mut(|m, map_base| {
+                if m.can_place_building(&map_base, coord) {
+                    m.show_ghost(&map_base, coord, building_type);
+                } else {
+                    m.hide_ghost(&map_base);
+                }
+            })
+            .unwrap();
+    }
+
+    #[method]
+    fn on_tile_unhovered(&mut self, #[base] base: &Node2D, coord: GridCoord) {
+        Self::get_map(base)
+            .map_mut(|m, map_base| {
+                if m.ghost() == Some(coord) {
+                    m.hide_ghost(&map_base);
+                }
+            })
+            .unwrap();
+    }
 
-        let b = Building::from_variant(&b).unwrap();
-        self.add_to_resource_per_turn(base, b.resource_type, b.resource_amount);
-        self.add_to_resource_per_turn(base, b.upkeep_type, 0 - b.upkeep_amount);
+    // Rebuilds every income_* from the buildings currently on the map.
+    #[method]
+    fn recalculate_income(&mut self, #[base] base: &Node2D) {
+        let (yields, grids) = self.compute_yields(base);
+        self.income = yields::total(&yields, &grids);
+        self.yields = yields;
+        self.power_grids = grids;
+        self.projection = None;
+        self.resources_changed(base);
+
+        Self::get_map(base)
+            .map_mut(|m, _| {
+                if self.power_grid && self.power_overlay {
+                    m.show_power_grids(&self.power_grids);
+                } else {
+                    m.hide_power_grids();
+                }
+            })
+            .unwrap();
+    }
+
+    #[method]
+    fn set_power_overlay(&mut self, #[base] base: &Node2D, visible: bool) {
+        self.power_overlay = visible;
+        self.recalculate_income(base);
     }
 
     #[method]
     fn end_turn(&mut self, #[base] base: &Node2D) {
-        self.current_energy += self.income_energy;
-        self.current_food += self.income_food;
-        self.current_metal += self.income_metal;
-        self.current_oxygen += self.current_oxygen;
+        let income = self.income;
+        for resource_type in ResourceType::STORED.iter() {
+            self.change_stock(resource_type, income.get(resource_type));
+        }
+        self.log_stock_changes(base, &income);
+        self.has_acted = false;
+        self.last_placement = None;
+        // Whatever was being chosen lapses with the turn.
+        self.is_placing_building = false;
+        self.is_scouting = false;
+        Self::get_map(base)
+            .map_mut(|m, map_base| m.disable_tile_highlights(&map_base))
+            .unwrap();
+        self.history.push(TurnSnapshot {
+            turn: self.turn_number,
+            stock: self.stock,
+            income,
+        });
         self.turn_number += 1;
+        self.resources_changed(base);
+        if self.turn_number % event_log::TURN_MILESTONE == 0 {
+            self.log(
+                base,
+                LogKind::Milestone,
+                tr_args(
+                    "The colony reached turn {turn}",
+                    &[("turn", &self.turn_number)],
+                ),
+            );
+        }
+    }
+
+    // Shortages, after a turn's income is paid.
+    fn log_stock_changes(&mut self, base: &Node2D, income: &ResourceAmounts) {
+        for resource_type in ResourceType::STORED.iter() {
+            let name = tr(resource_type.name());
+            let stock = self.stock.get(resource_type);
+            let change = income.get(resource_type);
+            if stock < 0 {
+                self.log(
+                    base,
+                    LogKind::Shortage,
+                    tr_args(
+                        "Out of {resource}, {amount} short",
+                        &[("resource", &name), ("amount", &-stock)],
+                    ),
+                );
+            } else if stock + change < 0 {
+                self.log(
+                    base,
+                    LogKind::Shortage,
+                    tr_args("{resource} will run out next turn", &[("resource", &name)]),
+                );
+            }
+        }
     }
 }
 // use godot_sane_defaults::kb2d_move_and_slide;
@@ -671,11 +3013,50 @@ impl GameManager {
 fn init(handle: InitHandle) {
     handle.add_class::<Building>();
     handle.add_class::<BuildingData>();
+    handle.add_class::<CameraController>();
     handle.add_class::<GameManager>();
-    handle.add_class::<Map>();
-    handle.add_class::<Tile>();
+    handle.add_class::<HistoryGraph>();
+    handle.add_tool_class::<Map>();
+    handle.add_class::<MapEditor>();
+    handle.add_class::<Minimap>();
+    handle.add_class::<SceneValidator>();
+    handle.add_tool_class::<Tile>();
+    handle.add_class::<TileCursor>();
     handle.add_class::<UI>();
 }
 
 // Creates entry-points of dyn lib.
 godot_init!(init);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_amounts_only_store_stored_resources() {
+        let mut amounts = ResourceAmounts::default();
+        amounts.add(&ResourceType::Metal, 3);
+        amounts.add(&ResourceType::Energy, -2);
+        amounts.add(&ResourceType::Nothing, 5);
+
+        assert_eq!(amounts.get(&ResourceType::Metal), 3);
+        assert_eq!(amounts.get(&ResourceType::Nothing), 0);
+        assert!(amounts.get_mut(&ResourceType::Nothing).is_none());
+        let listed: Vec<i32> = amounts.amounts().into_iter().map(|(_, a)| a).collect();
+        assert_eq!(listed, vec![0, 3, 0, -2]);
+    }
+
+    #[test]
+    fn resource_amounts_subtract_per_resource() {
+        let mut before = ResourceAmounts::default();
+        before.add(&ResourceType::Food, 4);
+        let mut after = before;
+        after.add(&ResourceType::Food, 1);
+        after.add(&ResourceType::Oxygen, 2);
+
+        let delta = after - before;
+        assert_eq!(delta.get(&ResourceType::Food), 1);
+        assert_eq!(delta.get(&ResourceType::Oxygen), 2);
+        assert_eq!(delta.get(&ResourceType::Metal), 0);
+    }
+}
@@ -0,0 +1,152 @@
+use gdnative::prelude::*;
+
+use crate::grid::GridCoord;
+use crate::i18n::{tr, tr_args};
+use crate::{BuildingType, Map, Terrain};
+
+// What a click on a tile does while the editor is active.
+#[derive(Clone, PartialEq)]
+pub enum EditorTool {
+    PaintTerrain(Terrain),
+    SetStartTile,
+    PlaceBuilding(BuildingType),
+    RemoveBuilding,
+}
+
+impl EditorTool {
+    // Every tool, in the order the debug menu lists them.
+    pub fn all() -> Vec<EditorTool> {
+        let mut tools: Vec<EditorTool> = Terrain::ALL
+            .iter()
+            .cloned()
+            .map(EditorTool::PaintTerrain)
+            .collect();
+        tools.push(EditorTool::SetStartTile);
+        // The Base always sits on the start tile, so it is placed by moving that instead.
+        tools.extend(
+            BuildingType::ALL
+                .iter()
+                .filter(|b| **b != BuildingType::Base)
+                .cloned()
+                .map(EditorTool::PlaceBuilding),
+        );
+        tools.push(EditorTool::RemoveBuilding);
+        tools
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            EditorTool::PaintTerrain(terrain) => {
+                tr_args("Paint {terrain}", &[("terrain", &tr(terrain.name()))])
+            }
+            EditorTool::SetStartTile => tr("Set start tile"),
+            EditorTool::PlaceBuilding(building) => {
+                tr_args("Place {building}", &[("building", &tr(building.name()))])
+            }
+            EditorTool::RemoveBuilding => tr("Remove building"),
+        }
+    }
+}
+
+// Lets designers paint the map in game. Clicks reach it through GameManager::on_tile_clicked
+// while it is active, and it writes straight into the Map's grid.
+#[derive(NativeClass)]
+#[inherit(Node)]
+pub struct MapEditor {
+    #[property(default = false)]
+    active: bool,
+    #[property]
+    save_path: String,
+    tool: EditorTool,
+}
+
+#[methods]
+impl MapEditor {
+    fn new(_base: &Node) -> Self {
+        MapEditor {
+            active: false,
+            save_path: "user://map.tmx".to_owned(),
+            tool: EditorTool::PaintTerrain(Terrain::Plains),
+        }
+    }
+
+    fn get_map(base: &Node) -> TInstance<'static, Map> {
+        unsafe {
+            base.get_node_as_instance::<Map>("/root/MainScene/Tiles")
+                .unwrap()
+        }
+    }
+
+    #[method]
+    fn _ready(&self, #[base] _base: &Node) {
+        godot_print!("Hello from Map Editor!")
+    }
+
+    #[method]
+    fn is_active(&self, #[base] _base: &Node) -> bool {
+        self.active
+    }
+
+    #[method]
+    fn set_active(&mut self, #[base] base: &Node, active: bool) {
+        self.active = active;
+        let game_manager = unsafe { base.get_node_as::<Node>("/root/MainScene").unwrap() };
+        if active {
+            godot_print!("Map editor on");
+            game_manager.set("is_placing_building", false);
+            game_manager.set("is_scouting", false);
+            Self::get_map(base)
+                .map_mut(|map, map_base| {
+                    map.disable_tile_highlights(&map_base);
+                    map.set_reveal_all(&map_base, true);
+                })
+                .unwrap();
+        } else {
+            godot_print!("Map editor off");
+            Self::get_map(base)
+                .map_mut(|map, map_base| map.set_reveal_all(&map_base, false))
+                .unwrap();
+            // Buildings may have been added or removed.
+            unsafe { game_manager.call("recalculate_income", &[]) };
+        }
+    }
+
+    #[method]
+    fn tool_labels(&self, #[base] _base: &Node) -> Vec<String> {
+        EditorTool::all().iter().map(|t| t.label()).collect()
+    }
+
+    #[method]
+    fn select_tool(&mut self, #[base] _base: &Node, index: i64) {
+        if let Some(tool) = EditorTool::all().get(index as usize) {
+            godot_print!("Editor tool: {}", tool.label());
+            self.tool = tool.clone();
+        }
+    }
+
+    #[method]
+    fn edit_tile(&self, #[base] base: &Node, coord: GridCoord) {
+        let tool = self.tool.clone();
+        Self::get_map(base)
+            .map_mut(|map, map_base| match tool {
+                EditorTool::PaintTerrain(terrain) => map.paint_terrain(&map_base, coord, terrain),
+                EditorTool::SetStartTile => map.set_start_tile(&map_base, coord),
+                EditorTool::PlaceBuilding(building) => {
+                    map.place_prebuilt(&map_base, coord, building)
+                }
+                EditorTool::RemoveBuilding => map.remove_building(&map_base, coord),
+            })
+            .unwrap();
+    }
+
+    #[method]
+    fn save(&self, #[base] base: &Node) {
+        let result = Self::get_map(base)
+            .map(|map, _| map.save_map(&self.save_path))
+            .unwrap();
+        match result {
+            Ok(()) => godot_print!("Saved map to {}", self.save_path),
+            Err(e) => godot_error!("Could not save map: {}", e),
+        }
+    }
+}
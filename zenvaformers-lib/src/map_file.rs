@@ -0,0 +1,896 @@
+use std::collections::HashMap;
+
+use gdnative::{
+    api::{File, Image},
+    prelude::*,
+};
+
+use crate::grid::{GridCoord, GridLayout};
+use crate::map_grid::{MapGrid, TileState};
+use crate::{BuildingType, ResourceType, Terrain};
+
+// A map read from disk. The layout is only known for formats that record it (Tiled does).
+pub struct ImportedMap {
+    pub grid: MapGrid,
+    pub layout: Option<GridLayout>,
+}
+
+// Picks the importer from the file extension.
+pub fn import_map(path: &str) -> Result<ImportedMap, String> {
+    let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "tmx" => parse_tmx(&read_text(path)?),
+        "csv" => parse_csv(&read_text(path)?).map(|grid| ImportedMap { grid, layout: None }),
+        "png" => import_png(path).map(|grid| ImportedMap { grid, layout: None }),
+        _ => Err(format!("{}: unsupported map format '{}'", path, extension)),
+    }
+}
+
+fn read_text(path: &str) -> Result<String, String> {
+    let file = File::new();
+    file.open(path, File::READ)
+        .map_err(|e| format!("{}: could not open ({:?})", path, e))?;
+    let text = file.get_as_text().to_string();
+    file.close();
+    Ok(text)
+}
+
+// One row of terrain codes per line, separated by commas or semicolons.
+// Codes are Terrain indices, so 0 is Plains and 3 is Crater.
+pub fn parse_csv(text: &str) -> Result<MapGrid, String> {
+    let rows = parse_code_rows(text)?;
+    let height = rows.len() as i32;
+    let width = rows.first().map(|r| r.len()).unwrap_or(0) as i32;
+    if width == 0 {
+        return Err("CSV map is empty".to_owned());
+    }
+
+    let mut grid = MapGrid::new(width, height);
+    for (y, row) in rows.iter().enumerate() {
+        if row.len() as i32 != width {
+            return Err(format!(
+                "CSV row {} has {} columns, expected {}",
+                y + 1,
+                row.len(),
+                width
+            ));
+        }
+        for (x, code) in row.iter().enumerate() {
+            let coord = GridCoord::new(x as i32, y as i32);
+            grid.get_mut(coord).unwrap().terrain = terrain_from_code(*code, coord)?;
+        }
+    }
+    Ok(grid)
+}
+
+fn parse_code_rows(text: &str) -> Result<Vec<Vec<i64>>, String> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.split(|c| c == ',' || c == ';')
+                .map(|cell| cell.trim())
+                .filter(|cell| !cell.is_empty())
+                .map(|cell| {
+                    cell.parse::<i64>()
+                        .map_err(|_| format!("'{}' is not a terrain code", cell))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn terrain_from_code(code: i64, coord: GridCoord) -> Result<Terrain, String> {
+    Terrain::from_index(code).ok_or_else(|| {
+        format!(
+            "unknown terrain code {} at ({}, {})",
+            code, coord.x, coord.y
+        )
+    })
+}
+
+// One pixel per tile, each coloured like the terrain it stands for. Read straight from the
+// file, since an imported texture only keeps its pixels with some import settings.
+fn import_png(path: &str) -> Result<MapGrid, String> {
+    let image = Image::new();
+    image
+        .load(path)
+        .map_err(|e| format!("{}: could not load image ({:?})", path, e))?;
+
+    let (width, height) = (image.get_width() as i32, image.get_height() as i32);
+    let mut grid = MapGrid::new(width, height);
+    image.lock();
+    for coord in grid.coords() {
+        let pixel = image.get_pixel(coord.x as i64, coord.y as i64);
+        grid.get_mut(coord).unwrap().terrain = Terrain::closest_to_color(pixel);
+    }
+    image.unlock();
+    Ok(grid)
+}
+
+// Tiled maps. The layer named "terrain" holds Terrain indices as tile ids, optional
+// "deposits" and "buildings" layers hold ResourceType and BuildingType indices, and an
+// object named or typed "start" marks the start tile. Layers must use CSV encoding.
+pub fn parse_tmx(text: &str) -> Result<ImportedMap, String> {
+    let mut map_attributes = HashMap::new();
+    let mut first_gid = None;
+    let mut layers: HashMap<String, String> = HashMap::new();
+    let mut start_object = None;
+    let mut current_layer: Option<String> = None;
+    let mut in_data = false;
+
+    for token in XmlTokens::new(text) {
+        match token {
+            XmlToken::Open { name, attributes } => match name.as_str() {
+                "map" => map_attributes = attributes,
+                // Both layers index into the first tileset.
+                "tileset" if first_gid.is_none() => {
+                    first_gid = attribute(&attributes, "firstgid");
+                }
+                "layer" => {
+                    current_layer = attributes.get("name").map(|n| n.to_lowercase());
+                }
+                "data" => {
+                    if let Some(encoding) = attributes.get("encoding") {
+                        if encoding != "csv" {
+                            return Err(format!(
+                                "layer data must be CSV encoded, found '{}'",
+                                encoding
+                            ));
+                        }
+                    }
+                    in_data = true;
+                }
+                "object" => {
+                    let is_start = ["name", "type", "class"].iter().any(|key| {
+                        attributes
+                            .get(*key)
+                            .map(|v| v.eq_ignore_ascii_case("start"))
+                            == Some(true)
+                    });
+                    if is_start {
+                        start_object = Some(attributes);
+                    }
+                }
+                _ => {}
+            },
+            XmlToken::Close { name } => match name.as_str() {
+                "data" => in_data = false,
+                "layer" => current_layer = None,
+                _ => {}
+            },
+            XmlToken::Text(data) => {
+                if let (true, Some(layer)) = (in_data, &current_layer) {
+                    layers.entry(layer.clone()).or_default().push_str(&data);
+                }
+            }
+        }
+    }
+
+    let width: i32 = attribute(&map_attributes, "width").ok_or("map has no width")?;
+    let height: i32 = attribute(&map_attributes, "height").ok_or("map has no height")?;
+    if width <= 0 || height <= 0 {
+        return Err(format!("map is {}x{} tiles", width, height));
+    }
+    let layout = tmx_layout(&map_attributes)?;
+    let first_gid: i64 = first_gid.unwrap_or(1);
+
+    let terrain = layers.get("terrain").ok_or("map has no 'terrain' layer")?;
+    let terrain = parse_gids(terrain, width, height, "terrain")?;
+    let mut grid = MapGrid::new(width, height);
+    for (coord, gid) in grid.coords().zip(terrain) {
+        grid.get_mut(coord).unwrap().terrain = terrain_from_code(gid - first_gid, coord)?;
+    }
+
+    if let Some(deposits) = layers.get("deposits") {
+        let deposits = parse_gids(deposits, width, height, "deposits")?;
+        for (coord, gid) in grid.coords().zip(deposits) {
+            // gid 0 is an empty cell in Tiled.
+            if gid > 0 {
+                grid.get_mut(coord).unwrap().deposit = ResourceType::from_index(gid - first_gid)
+                    .ok_or_else(|| {
+                        format!("unknown deposit {} at ({}, {})", gid, coord.x, coord.y)
+                    })?;
+            }
+        }
+    }
+
+    if let Some(buildings) = layers.get("buildings") {
+        let buildings = parse_gids(buildings, width, height, "buildings")?;
+        for (coord, gid) in grid.coords().zip(buildings) {
+            if gid > 0 {
+                let building = BuildingType::from_index(gid - first_gid).ok_or_else(|| {
+                    format!("unknown building {} at ({}, {})", gid, coord.x, coord.y)
+                })?;
+                grid.get_mut(coord).unwrap().building = Some(building);
+            }
+        }
+    }
+
+    if let Some(object) = start_object {
+        let metrics = TiledMetrics::from_map(&map_attributes)?;
+        let x: f32 = attribute(&object, "x").unwrap_or(0.0);
+        let y: f32 = attribute(&object, "y").unwrap_or(0.0);
+        let w: f32 = attribute(&object, "width").unwrap_or(0.0);
+        let h: f32 = attribute(&object, "height").unwrap_or(0.0);
+        let coord = metrics.cell(layout, Vector2::new(x + w * 0.5, y + h * 0.5));
+        match grid.get_mut(coord) {
+            Some(tile) => tile.start_tile = true,
+            None => {
+                return Err(format!(
+                    "start object lies outside the map at ({}, {})",
+                    x, y
+                ))
+            }
+        }
+    }
+
+    Ok(ImportedMap {
+        grid,
+        layout: Some(layout),
+    })
+}
+
+// Enough tile ids for the longest of Terrain, ResourceType and BuildingType.
+const TILESET_TILES: i32 = 5;
+
+// Writes the map and, next to it, the image its tileset uses: "map.tmx" gets "map_tiles.png".
+pub fn export_tmx(
+    path: &str,
+    grid: &MapGrid,
+    layout: GridLayout,
+    tile_size: f32,
+) -> Result<(), String> {
+    let image_path = format!("{}_tiles.png", path.strip_suffix(".tmx").unwrap_or(path));
+    let image_name = image_path.rsplit('/').next().unwrap_or(&image_path);
+    let metrics = TiledMetrics::for_layout(layout, tile_size as i32);
+    let (tile_width, tile_height) = (metrics.tile_width, metrics.tile_height);
+
+    // One swatch per tile id, coloured like the terrain it stands for in the terrain layer.
+    let image = Image::new();
+    image.create(
+        (tile_width as i32 * TILESET_TILES) as i64,
+        tile_height as i64,
+        false,
+        Image::FORMAT_RGBA8,
+    );
+    for id in 0..TILESET_TILES {
+        let color = Terrain::from_index(id as i64)
+            .map(|t| t.get_color())
+            .or_else(|| ResourceType::from_index(id as i64).map(|r| r.get_color()))
+            .unwrap_or(Color::from_rgb(1.0, 1.0, 1.0));
+        let swatch = Rect2::new(
+            Vector2::new(id as f32 * tile_width, 0.0),
+            Vector2::new(tile_width, tile_height),
+        );
+        image.fill_rect(swatch, color);
+    }
+    image
+        .save_png(image_path.as_str())
+        .map_err(|e| format!("{}: could not save tileset ({:?})", image_path, e))?;
+
+    let file = File::new();
+    file.open(path, File::WRITE)
+        .map_err(|e| format!("{}: could not open for writing ({:?})", path, e))?;
+    file.store_string(write_tmx(grid, layout, tile_size, image_name));
+    file.close();
+    Ok(())
+}
+
+// Writes the same shape of map parse_tmx reads, with an embedded tileset whose tile ids
+// follow the Terrain, ResourceType and BuildingType indices.
+pub fn write_tmx(
+    grid: &MapGrid,
+    layout: GridLayout,
+    tile_size: f32,
+    tileset_image: &str,
+) -> String {
+    let metrics = TiledMetrics::for_layout(layout, tile_size as i32);
+    let (tile_width, tile_height) = (metrics.tile_width, metrics.tile_height);
+    let orientation = match layout {
+        GridLayout::Square => r#"orientation="orthogonal""#.to_owned(),
+        GridLayout::Isometric => r#"orientation="isometric""#.to_owned(),
+        GridLayout::PointyHex | GridLayout::FlatHex => format!(
+            r#"orientation="hexagonal" hexsidelength="{}" staggeraxis="{}" staggerindex="odd""#,
+            metrics.hex_side,
+            if layout == GridLayout::PointyHex {
+                "y"
+            } else {
+                "x"
+            }
+        ),
+    };
+    let (width, height) = (grid.width(), grid.height());
+
+    let mut tmx = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    tmx += &format!(
+        "<map version=\"1.10\" {} renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" infinite=\"0\">\n",
+        orientation, width, height, tile_width, tile_height
+    );
+    tmx += &format!(
+        " <tileset firstgid=\"1\" name=\"zenvaformers\" tilewidth=\"{}\" tileheight=\"{}\" tilecount=\"{}\" columns=\"{}\">\n  <image source=\"{}\" width=\"{}\" height=\"{}\"/>\n </tileset>\n",
+        tile_width,
+        tile_height,
+        TILESET_TILES,
+        TILESET_TILES,
+        tileset_image,
+        tile_width * TILESET_TILES as f32,
+        tile_height
+    );
+
+    // gid 0 leaves a cell empty, so every index is shifted up by the firstgid of 1.
+    let layers: [(&str, fn(&TileState) -> i64); 3] = [
+        ("terrain", |t| t.terrain.clone() as i64 + 1),
+        ("deposits", |t| match t.deposit {
+            ResourceType::Nothing => 0,
+            ref deposit => deposit.clone() as i64 + 1,
+        }),
+        ("buildings", |t| {
+            t.building
+                .as_ref()
+                .map(|b| b.clone() as i64 + 1)
+                .unwrap_or(0)
+        }),
+    ];
+    for (id, (name, gid_of)) in layers.iter().enumerate() {
+        let rows: Vec<String> = (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| gid_of(grid.get(GridCoord::new(x, y)).unwrap()).to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect();
+        tmx += &format!(
+            " <layer id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\">\n  <data encoding=\"csv\">\n{}\n</data>\n </layer>\n",
+            id + 1,
+            name,
+            width,
+            height,
+            rows.join(",\n")
+        );
+    }
+
+    // A rectangle covering the start tile, in the object coordinates parse_tmx reads back.
+    if let Some(start) = grid.start_tile() {
+        let centre = metrics.centre(layout, start);
+        let (w, h) = match layout {
+            GridLayout::Isometric => (tile_height, tile_height),
+            _ => (tile_width, tile_height),
+        };
+        tmx += &format!(
+            " <objectgroup id=\"4\" name=\"objects\">\n  <object id=\"1\" name=\"start\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>\n </objectgroup>\n",
+            centre.x - w * 0.5,
+            centre.y - h * 0.5,
+            w,
+            h
+        );
+    }
+    tmx += "</map>\n";
+    tmx
+}
+
+fn tmx_layout(map: &HashMap<String, String>) -> Result<GridLayout, String> {
+    let orientation = map
+        .get("orientation")
+        .map(|o| o.as_str())
+        .unwrap_or("orthogonal");
+    let stagger_axis = map.get("staggeraxis").map(|a| a.as_str()).unwrap_or("y");
+    match (orientation, stagger_axis) {
+        ("orthogonal", _) => Ok(GridLayout::Square),
+        ("isometric", _) => Ok(GridLayout::Isometric),
+        // GridLayout shoves the odd rows or columns, like Tiled's default.
+        ("hexagonal", _) if map.get("staggerindex").map(|i| i.as_str()) == Some("even") => {
+            Err("hexagonal maps must use staggerindex=\"odd\"".to_owned())
+        }
+        ("hexagonal", "y") => Ok(GridLayout::PointyHex),
+        ("hexagonal", "x") => Ok(GridLayout::FlatHex),
+        _ => Err(format!("unsupported map orientation '{}'", orientation)),
+    }
+}
+
+// Pixel sizes Tiled lays out a map's tiles and objects with.
+struct TiledMetrics {
+    tile_width: f32,
+    tile_height: f32,
+    // Length of a hex's flat sides along the stagger axis.
+    hex_side: f32,
+}
+
+impl TiledMetrics {
+    // What write_tmx lays a map out with: square tiles, diamonds twice as wide as they are
+    // tall, and hexes whose flat sides are half a tile, which matches GridLayout::to_world.
+    fn for_layout(layout: GridLayout, tile_size: i32) -> Self {
+        let size = tile_size.max(2) as f32;
+        TiledMetrics {
+            tile_width: size,
+            tile_height: match layout {
+                GridLayout::Isometric => (size * 0.5).floor(),
+                _ => size,
+            },
+            hex_side: match layout {
+                GridLayout::PointyHex | GridLayout::FlatHex => (size * 0.5).floor(),
+                _ => 0.0,
+            },
+        }
+    }
+
+    fn from_map(map: &HashMap<String, String>) -> Result<Self, String> {
+        let metrics = TiledMetrics {
+            tile_width: attribute(map, "tilewidth").ok_or("map has no tilewidth")?,
+            tile_height: attribute(map, "tileheight").ok_or("map has no tileheight")?,
+            hex_side: attribute(map, "hexsidelength").unwrap_or(0.0),
+        };
+        if metrics.tile_width <= 0.0 || metrics.tile_height <= 0.0 {
+            return Err("map tiles have no size".to_owned());
+        }
+        Ok(metrics)
+    }
+
+    // Centre of a tile in object coordinates. Isometric maps keep objects in an
+    // unprojected space where every tile is tile_height square; the others use pixels.
+    fn centre(&self, layout: GridLayout, coord: GridCoord) -> Vector2 {
+        let (x, y) = (coord.x as f32, coord.y as f32);
+        let (w, h) = (self.tile_width, self.tile_height);
+        match layout {
+            GridLayout::Square => Vector2::new((x + 0.5) * w, (y + 0.5) * h),
+            GridLayout::Isometric => Vector2::new((x + 0.5) * h, (y + 0.5) * h),
+            GridLayout::PointyHex => {
+                let shove = if coord.y & 1 == 1 { 0.5 } else { 0.0 };
+                let row_height = (h + self.hex_side) * 0.5;
+                Vector2::new((x + shove + 0.5) * w, y * row_height + h * 0.5)
+            }
+            GridLayout::FlatHex => {
+                let shove = if coord.x & 1 == 1 { 0.5 } else { 0.0 };
+                let column_width = (w + self.hex_side) * 0.5;
+                Vector2::new(x * column_width + w * 0.5, (y + shove + 0.5) * h)
+            }
+        }
+    }
+
+    // The tile an object coordinate falls in.
+    fn cell(&self, layout: GridLayout, position: Vector2) -> GridCoord {
+        let (w, h) = (self.tile_width, self.tile_height);
+        let guess = match layout {
+            GridLayout::Square => {
+                return GridCoord::new(
+                    (position.x / w).floor() as i32,
+                    (position.y / h).floor() as i32,
+                )
+            }
+            GridLayout::Isometric => {
+                return GridCoord::new(
+                    (position.x / h).floor() as i32,
+                    (position.y / h).floor() as i32,
+                )
+            }
+            GridLayout::PointyHex => {
+                let row = ((position.y - h * 0.5) / ((h + self.hex_side) * 0.5)).round() as i32;
+                let shove = if row & 1 == 1 { 0.5 } else { 0.0 };
+                GridCoord::new((position.x / w - 0.5 - shove).round() as i32, row)
+            }
+            GridLayout::FlatHex => {
+                let column = ((position.x - w * 0.5) / ((w + self.hex_side) * 0.5)).round() as i32;
+                let shove = if column & 1 == 1 { 0.5 } else { 0.0 };
+                GridCoord::new(column, (position.y / h - 0.5 - shove).round() as i32)
+            }
+        };
+        // Hexes don't fill their rounding boxes, so settle it on the nearest centre.
+        let distance = |coord: &GridCoord| {
+            let centre = self.centre(layout, *coord);
+            (centre.x - position.x).powi(2) + (centre.y - position.y).powi(2)
+        };
+        std::iter::once(guess)
+            .chain(layout.neighbours(guess))
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .unwrap_or(guess)
+    }
+}
+
+// Tiled keeps a tile's flips and rotation in the top three bits of its gid.
+const GID_FLAGS_MASK: i64 = 0x1FFF_FFFF;
+
+fn parse_gids(data: &str, width: i32, height: i32, layer: &str) -> Result<Vec<i64>, String> {
+    let gids = data
+        .split(',')
+        .map(|cell| cell.trim())
+        .filter(|cell| !cell.is_empty())
+        .map(|cell| {
+            cell.parse::<i64>()
+                .map(|gid| gid & GID_FLAGS_MASK)
+                .map_err(|_| format!("'{}' in layer '{}' is not a tile id", cell, layer))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if gids.len() as i64 != width as i64 * height as i64 {
+        return Err(format!(
+            "layer '{}' has {} tiles, expected {}",
+            layer,
+            gids.len(),
+            width as i64 * height as i64
+        ));
+    }
+    Ok(gids)
+}
+
+fn attribute<T: std::str::FromStr>(attributes: &HashMap<String, String>, key: &str) -> Option<T> {
+    attributes.get(key).and_then(|v| v.trim().parse().ok())
+}
+
+enum XmlToken {
+    Open {
+        name: String,
+        attributes: HashMap<String, String>,
+    },
+    Close {
+        name: String,
+    },
+    Text(String),
+}
+
+// Just enough XML to read what Tiled writes: elements, attributes and text.
+// Self-closing elements produce an Open followed by a Close.
+struct XmlTokens<'a> {
+    rest: &'a str,
+    pending_close: Option<String>,
+}
+
+impl<'a> XmlTokens<'a> {
+    fn new(text: &'a str) -> Self {
+        XmlTokens {
+            rest: text,
+            pending_close: None,
+        }
+    }
+}
+
+impl<'a> Iterator for XmlTokens<'a> {
+    type Item = XmlToken;
+
+    fn next(&mut self) -> Option<XmlToken> {
+        if let Some(name) = self.pending_close.take() {
+            return Some(XmlToken::Close { name });
+        }
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            if !self.rest.starts_with('<') {
+                let end = self.rest.find('<').unwrap_or(self.rest.len());
+                let text = &self.rest[..end];
+                self.rest = &self.rest[end..];
+                if !text.trim().is_empty() {
+                    return Some(XmlToken::Text(text.to_owned()));
+                }
+                continue;
+            }
+
+            // Declarations, comments and doctypes carry nothing we need.
+            if self.rest.starts_with("<?") || self.rest.starts_with("<!") {
+                let terminator = if self.rest.starts_with("<!--") {
+                    "-->"
+                } else {
+                    ">"
+                };
+                let end = self
+                    .rest
+                    .find(terminator)
+                    .map(|i| i + terminator.len())
+                    .unwrap_or(self.rest.len());
+                self.rest = &self.rest[end..];
+                continue;
+            }
+
+            let end = self.rest.find('>').unwrap_or(self.rest.len());
+            let tag = &self.rest[1..end];
+            self.rest = &self.rest[(end + 1).min(self.rest.len())..];
+
+            if let Some(name) = tag.strip_prefix('/') {
+                return Some(XmlToken::Close {
+                    name: name.trim().to_owned(),
+                });
+            }
+            let self_closing = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let name = tag[..name_end].to_owned();
+            if self_closing {
+                self.pending_close = Some(name.clone());
+            }
+            return Some(XmlToken::Open {
+                name,
+                attributes: parse_attributes(&tag[name_end..]),
+            });
+        }
+    }
+}
+
+fn parse_attributes(mut text: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    while let Some(eq) = text.find('=') {
+        let key = text[..eq].trim().to_owned();
+        let after = text[eq + 1..].trim_start();
+        let quote = match after.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => break,
+        };
+        let value_end = match after[1..].find(quote) {
+            Some(i) => i + 1,
+            None => break,
+        };
+        attributes.insert(key, unescape(&after[1..value_end]));
+        text = &after[value_end + 1..];
+    }
+    attributes
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tmx(map: &str, body: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<map {}>\n <tileset firstgid=\"1\" source=\"t.tsx\"/>\n{}</map>\n",
+            map, body
+        )
+    }
+
+    fn layer(name: &str, data: &str) -> String {
+        format!(
+            " <layer name=\"{}\">\n  <data encoding=\"csv\">\n{}\n</data>\n </layer>\n",
+            name, data
+        )
+    }
+
+    fn start_object(attributes: &str) -> String {
+        format!(
+            " <objectgroup name=\"objects\">\n  <object id=\"1\" name=\"start\" {}/>\n </objectgroup>\n",
+            attributes
+        )
+    }
+
+    fn terrain_codes(grid: &MapGrid) -> Vec<i64> {
+        grid.coords()
+            .map(|c| grid.get(c).unwrap().terrain.clone() as i64)
+            .collect()
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let text = "0,1,2,3\n3;2;1;0\n\n1, 1, 0, 2\n";
+        let grid = parse_csv(text).unwrap();
+        assert_eq!((grid.width(), grid.height()), (4, 3));
+
+        let written: Vec<String> = (0..grid.height())
+            .map(|y| {
+                (0..grid.width())
+                    .map(|x| {
+                        (grid.get(GridCoord::new(x, y)).unwrap().terrain.clone() as i64).to_string()
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect();
+        let again = parse_csv(&written.join("\n")).unwrap();
+        assert_eq!(terrain_codes(&again), terrain_codes(&grid));
+        assert_eq!(
+            terrain_codes(&grid),
+            vec![0, 1, 2, 3, 3, 2, 1, 0, 1, 1, 0, 2]
+        );
+    }
+
+    #[test]
+    fn orthogonal_tmx() {
+        let text = tmx(
+            r#"orientation="orthogonal" width="3" height="2" tilewidth="32" tileheight="32""#,
+            &(layer("terrain", "1,2,3,\n4,1,1")
+                + &layer("deposits", "0,3,0,\n0,0,0")
+                + &layer("buildings", "0,0,0,\n0,2,0")
+                + &start_object(r#"x="64" y="32" width="32" height="32""#)),
+        );
+        let map = parse_tmx(&text).unwrap();
+        assert!(map.layout == Some(GridLayout::Square));
+        let grid = map.grid;
+        assert_eq!(terrain_codes(&grid), vec![0, 1, 2, 3, 0, 0]);
+        assert!(grid.get(GridCoord::new(1, 0)).unwrap().deposit == ResourceType::Metal);
+        assert!(grid.get(GridCoord::new(1, 1)).unwrap().building == Some(BuildingType::Mine));
+        assert_eq!(grid.start_tile(), Some(GridCoord::new(2, 1)));
+    }
+
+    #[test]
+    fn isometric_start_object() {
+        // Isometric objects sit in an unprojected space of tileheight squares, so pixel
+        // maths on tilewidth would land on (1, 3).
+        let text = tmx(
+            r#"orientation="isometric" width="4" height="4" tilewidth="64" tileheight="32""#,
+            &(layer("terrain", &["1"; 16].join(","))
+                + &start_object(r#"x="64" y="96" width="32" height="32""#)),
+        );
+        let map = parse_tmx(&text).unwrap();
+        assert!(map.layout == Some(GridLayout::Isometric));
+        assert_eq!(map.grid.start_tile(), Some(GridCoord::new(2, 3)));
+    }
+
+    #[test]
+    fn hexagonal_start_object() {
+        // Row 1 is shoved half a tile right and rows are 48 px apart.
+        let text = tmx(
+            r#"orientation="hexagonal" width="4" height="3" tilewidth="64" tileheight="64" hexsidelength="32" staggeraxis="y" staggerindex="odd""#,
+            &(layer("terrain", &["1"; 12].join(",")) + &start_object(r#"x="128" y="80""#)),
+        );
+        let map = parse_tmx(&text).unwrap();
+        assert!(map.layout == Some(GridLayout::PointyHex));
+        assert_eq!(map.grid.start_tile(), Some(GridCoord::new(1, 1)));
+
+        let text = tmx(
+            r#"orientation="hexagonal" width="4" height="3" tilewidth="64" tileheight="64" hexsidelength="32" staggeraxis="x" staggerindex="odd""#,
+            &(layer("terrain", &["1"; 12].join(","))
+                + &start_object(r#"x="136" y="40" width="16" height="16""#)),
+        );
+        let map = parse_tmx(&text).unwrap();
+        assert!(map.layout == Some(GridLayout::FlatHex));
+        assert_eq!(map.grid.start_tile(), Some(GridCoord::new(2, 0)));
+    }
+
+    #[test]
+    fn flipped_gids() {
+        // Flipped horizontally, vertically, diagonally and all three.
+        let text = tmx(
+            r#"width="4" height="1" tilewidth="32" tileheight="32""#,
+            &(layer("terrain", "2147483649,1073741826,536870915,3758096388")
+                + &layer("deposits", "0,2147483650,0,0")),
+        );
+        let grid = parse_tmx(&text).unwrap().grid;
+        assert_eq!(terrain_codes(&grid), vec![0, 1, 2, 3]);
+        assert!(grid.get(GridCoord::new(1, 0)).unwrap().deposit == ResourceType::Food);
+    }
+
+    #[test]
+    fn xml_tokens() {
+        let tokens: Vec<XmlToken> = XmlTokens::new(
+            "<?xml version='1.0'?><!-- a > comment --><a b='x &amp; y' c=\"2\"><c/>text</a>",
+        )
+        .collect();
+        assert_eq!(tokens.len(), 5);
+        match &tokens[0] {
+            XmlToken::Open { name, attributes } => {
+                assert_eq!(name, "a");
+                assert_eq!(attributes.get("b").map(|v| v.as_str()), Some("x & y"));
+                assert_eq!(attributes.get("c").map(|v| v.as_str()), Some("2"));
+            }
+            _ => panic!("expected <a>"),
+        }
+        assert!(matches!(&tokens[1], XmlToken::Open { name, .. } if name == "c"));
+        assert!(matches!(&tokens[2], XmlToken::Close { name } if name == "c"));
+        assert!(matches!(&tokens[3], XmlToken::Text(text) if text == "text"));
+        assert!(matches!(&tokens[4], XmlToken::Close { name } if name == "a"));
+    }
+
+    // A small map with a bit of everything, starting at `start`.
+    fn sample_grid(start: GridCoord) -> MapGrid {
+        let mut grid = MapGrid::new(5, 4);
+        for (i, coord) in grid.coords().collect::<Vec<_>>().into_iter().enumerate() {
+            let tile = grid.get_mut(coord).unwrap();
+            tile.terrain = Terrain::from_index(i as i64 % 4).unwrap();
+            tile.deposit = ResourceType::from_index(i as i64 % 5).unwrap();
+            tile.building = BuildingType::from_index(i as i64 % 7);
+        }
+        grid.get_mut(start).unwrap().start_tile = true;
+        grid
+    }
+
+    #[test]
+    fn write_then_parse_every_layout() {
+        let layouts = [
+            GridLayout::Square,
+            GridLayout::Isometric,
+            GridLayout::PointyHex,
+            GridLayout::FlatHex,
+        ];
+        for layout in layouts {
+            // Every tile as the start, so odd rows and columns are covered too.
+            for start in MapGrid::new(5, 4).coords() {
+                let grid = sample_grid(start);
+                let text = write_tmx(&grid, layout, 64.0, "map_tiles.png");
+                let map = parse_tmx(&text).unwrap();
+                assert!(map.layout == Some(layout), "{:?}", layout);
+
+                let read = map.grid;
+                assert_eq!((read.width(), read.height()), (5, 4));
+                assert_eq!(read.start_tile(), Some(start), "{:?}", layout);
+                for coord in grid.coords() {
+                    let (a, b) = (grid.get(coord).unwrap(), read.get(coord).unwrap());
+                    assert!(a.terrain == b.terrain, "{:?} {:?}", layout, coord);
+                    assert!(a.deposit == b.deposit, "{:?} {:?}", layout, coord);
+                    assert!(a.building == b.building, "{:?} {:?}", layout, coord);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn written_tileset_is_embedded() {
+        let text = write_tmx(
+            &sample_grid(GridCoord::new(0, 0)),
+            GridLayout::Isometric,
+            64.0,
+            "map_tiles.png",
+        );
+        assert!(!text.contains(".tsx"));
+        assert!(text.contains(r#"tilewidth="64" tileheight="32" tilecount="5" columns="5""#));
+        assert!(text.contains(r#"<image source="map_tiles.png" width="320" height="32"/>"#));
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        for text in ["", " \n", "0,1\n0", "0,x", "0,9", "-1"] {
+            assert!(parse_csv(text).is_err(), "CSV {:?}", text);
+        }
+
+        let size = r#"width="2" height="1" tilewidth="32" tileheight="32""#;
+        let broken = [
+            String::new(),
+            "not xml at all".to_owned(),
+            "<<<>>>/<".to_owned(),
+            "<map width=\"2".to_owned(),
+            "<é".to_owned(),
+            tmx(size, ""),
+            tmx(size, &layer("terrain", "1")),
+            tmx(size, &layer("terrain", "1,2,3")),
+            tmx(size, &layer("terrain", "1,x")),
+            tmx(size, &layer("terrain", "1,9")),
+            tmx(
+                size,
+                &(layer("terrain", "1,1") + &layer("deposits", "0,99")),
+            ),
+            tmx(
+                size,
+                &(layer("terrain", "1,1") + &layer("buildings", "99,0")),
+            ),
+            tmx(
+                size,
+                " <layer name=\"terrain\"><data encoding=\"base64\">AAAA</data></layer>\n",
+            ),
+            tmx(
+                size,
+                &(layer("terrain", "1,1") + &start_object(r#"x="500" y="0""#)),
+            ),
+            tmx(
+                size,
+                &(layer("terrain", "1,1") + &start_object(r#"x="-40" y="0""#)),
+            ),
+            tmx(r#"width="0" height="0""#, &layer("terrain", "")),
+            tmx(r#"width="-2" height="1""#, &layer("terrain", "1,1")),
+            tmx(
+                r#"width="99999999" height="99999999""#,
+                &layer("terrain", "1,1"),
+            ),
+            tmx(
+                r#"orientation="staggered" width="2" height="1""#,
+                &layer("terrain", "1,1"),
+            ),
+            tmx(
+                r#"orientation="hexagonal" staggerindex="even" width="2" height="1""#,
+                &layer("terrain", "1,1"),
+            ),
+            tmx(
+                r#"width="2" height="1""#,
+                &(layer("terrain", "1,1") + &start_object(r#"x="0" y="0""#)),
+            ),
+        ];
+        // Cut off part way through the terrain data.
+        let whole = tmx(size, &layer("terrain", "1,1"));
+        let cut = whole[..whole.find("1,1").unwrap() + 2].to_owned();
+        for text in broken.iter().chain([&cut]) {
+            assert!(parse_tmx(text).is_err(), "TMX {:?}", text);
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::{HashSet, VecDeque};
+
+use gdnative::prelude::*;
+
+use crate::grid::{GridCoord, GridLayout};
+use crate::noise::ValueNoise;
+use crate::{BuildingType, ResourceType, Terrain};
+
+// How much of the noise field a single tile covers.
+const TERRAIN_NOISE_SCALE: f32 = 0.15;
+const DEPOSIT_NOISE_SCALE: f32 = 0.45;
+const DEPOSIT_THRESHOLD: f32 = 0.78;
+
+// Everything there is to know about one tile. Tile nodes only ever draw this.
+#[derive(Clone, ToVariant, FromVariant)]
+pub struct TileState {
+    pub terrain: Terrain,
+    pub deposit: ResourceType,
+    pub building: Option<BuildingType>,
+    pub start_tile: bool,
+    pub highlighted: bool,
+    // Whether the building here reaches the Base through other buildings.
+    pub connected: bool,
+    // Power grid overlay: the grid's colour, and its surplus on one tile per grid.
+    pub overlay: Option<Color>,
+    pub overlay_text: String,
+    // Terrain and deposits stay hidden until something explores the tile.
+    pub explored: bool,
+    // A building drawn see-through while the player decides where to place it.
+    pub ghost: Option<BuildingType>,
+}
+
+impl TileState {
+    pub fn new(terrain: Terrain, deposit: ResourceType) -> Self {
+        TileState {
+            terrain,
+            deposit,
+            building: None,
+            start_tile: false,
+            highlighted: false,
+            connected: false,
+            overlay: None,
+            overlay_text: String::new(),
+            explored: false,
+            ghost: None,
+        }
+    }
+
+    pub fn is_buildable(&self) -> bool {
+        self.explored && self.building.is_none()
+    }
+}
+
+// The authoritative map: a width * height rectangle of tiles, stored row by row.
+#[derive(Clone)]
+pub struct MapGrid {
+    width: i32,
+    height: i32,
+    cells: Vec<TileState>,
+}
+
+impl MapGrid {
+    pub fn new(width: i32, height: i32) -> Self {
+        let (width, height) = (width.max(0), height.max(0));
+        MapGrid {
+            width,
+            height,
+            cells: vec![
+                TileState::new(Terrain::Plains, ResourceType::Nothing);
+                (width * height) as usize
+            ],
+        }
+    }
+
+    pub fn generate(width: i32, height: i32, seed: i64, layout: GridLayout) -> Self {
+        let mut grid = MapGrid::new(width, height);
+        let elevation = ValueNoise::new(seed);
+        let richness = ValueNoise::new(seed.wrapping_add(1));
+
+        for (i, cell) in grid.cells.iter_mut().enumerate() {
+            let (x, y) = ((i as i32 % width) as f32, (i as i32 / width) as f32);
+            cell.terrain = Terrain::from_elevation(elevation.fractal(
+                x * TERRAIN_NOISE_SCALE,
+                y * TERRAIN_NOISE_SCALE,
+                3,
+            ));
+            cell.deposit = Self::deposit_for(
+                &cell.terrain,
+                richness.sample(x * DEPOSIT_NOISE_SCALE, y * DEPOSIT_NOISE_SCALE),
+            );
+        }
+
+        grid.ensure_start_tile(layout);
+        grid
+    }
+
+    // Marks a start tile if the map doesn't already have one.
+    pub fn ensure_start_tile(&mut self, layout: GridLayout) {
+        if self.start_tile().is_some() {
+            return;
+        }
+        match self.choose_start_tile(layout) {
+            Some(start) => self.get_mut(start).unwrap().start_tile = true,
+            None => godot_warn!("Map has no tiles to put the Base on"),
+        }
+    }
+
+    fn deposit_for(terrain: &Terrain, richness: f32) -> ResourceType {
+        if richness < DEPOSIT_THRESHOLD {
+            return ResourceType::Nothing;
+        }
+        match terrain {
+            Terrain::Plains => ResourceType::Food,
+            Terrain::Dunes | Terrain::Rock => ResourceType::Metal,
+            Terrain::Crater => ResourceType::Nothing,
+        }
+    }
+
+    // The tile closest to the centre, so the Base has room to grow in every direction.
+    pub fn choose_start_tile(&self, layout: GridLayout) -> Option<GridCoord> {
+        let centre = GridCoord::new(self.width / 2, self.height / 2);
+        self.coords()
+            .min_by_key(|coord| layout.distance(*coord, centre))
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn in_bounds(&self, coord: GridCoord) -> bool {
+        coord.x >= 0 && coord.y >= 0 && coord.x < self.width && coord.y < self.height
+    }
+
+    fn index_of(&self, coord: GridCoord) -> Option<usize> {
+        if self.in_bounds(coord) {
+            Some((coord.y * self.width + coord.x) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, coord: GridCoord) -> Option<&TileState> {
+        self.index_of(coord).map(|i| &self.cells[i])
+    }
+
+    pub fn get_mut(&mut self, coord: GridCoord) -> Option<&mut TileState> {
+        self.index_of(coord).map(move |i| &mut self.cells[i])
+    }
+
+    pub fn coords(&self) -> impl Iterator<Item = GridCoord> {
+        let width = self.width.max(1);
+        (0..self.cells.len() as i32).map(move |i| GridCoord::new(i % width, i / width))
+    }
+
+    pub fn start_tile(&self) -> Option<GridCoord> {
+        self.coords().find(|c| self.get(*c).unwrap().start_tile)
+    }
+
+    pub fn buildings(&self) -> impl Iterator<Item = (GridCoord, &BuildingType)> + '_ {
+        self.coords()
+            .zip(self.cells.iter())
+            .filter_map(|(coord, cell)| cell.building.as_ref().map(|b| (coord, b)))
+    }
+
+    // Breadth first search out from the start tile along neighbouring buildings (conduits
+    // included). Sets `connected` on every tile and returns the tiles where it changed.
+    pub fn update_connectivity(&mut self, layout: GridLayout) -> Vec<GridCoord> {
+        let mut reached = HashSet::new();
+        let mut queue = VecDeque::new();
+        if let Some(start) = self.start_tile() {
+            if self.get(start).unwrap().building.is_some() {
+                reached.insert(start);
+                queue.push_back(start);
+            }
+        }
+        while let Some(coord) = queue.pop_front() {
+            for neighbour in layout.neighbours(coord) {
+                let has_building = self.get(neighbour).map(|t| t.building.is_some());
+                if has_building == Some(true) && reached.insert(neighbour) {
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        let mut changed = Vec::new();
+        for coord in self.coords().collect::<Vec<_>>() {
+            let state = self.get_mut(coord).unwrap();
+            let connected = reached.contains(&coord);
+            if state.connected != connected {
+                state.connected = connected;
+                changed.push(coord);
+            }
+        }
+        changed
+    }
+
+    // The tiles that a new building on `coord` would connect to the Base: `coord` itself
+    // and every unconnected building it links up. Empty if it wouldn't be connected.
+    pub fn connected_by(&self, coord: GridCoord, layout: GridLayout) -> Vec<GridCoord> {
+        let reaches_base = self.start_tile() == Some(coord)
+            || layout
+                .neighbours(coord)
+                .into_iter()
+                .any(|n| self.get(n).map(|t| t.connected) == Some(true));
+        if !reaches_base {
+            return Vec::new();
+        }
+        let mut reached = vec![coord];
+        let mut seen: HashSet<GridCoord> = reached.iter().cloned().collect();
+        let mut queue: VecDeque<GridCoord> = reached.iter().cloned().collect();
+        while let Some(current) = queue.pop_front() {
+            for neighbour in layout.neighbours(current) {
+                let joins = self
+                    .get(neighbour)
+                    .map(|t| t.building.is_some() && !t.connected);
+                if joins == Some(true) && seen.insert(neighbour) {
+                    reached.push(neighbour);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        reached
+    }
+
+    // Explores every tile within `radius` steps of `centre`, returning the newly explored ones.
+    pub fn explore(
+        &mut self,
+        centre: GridCoord,
+        radius: i32,
+        layout: GridLayout,
+    ) -> Vec<GridCoord> {
+        let mut revealed = Vec::new();
+        for coord in self.coords().collect::<Vec<_>>() {
+            let state = self.get_mut(coord).unwrap();
+            if !state.explored && layout.distance(centre, coord) <= radius {
+                state.explored = true;
+                revealed.push(coord);
+            }
+        }
+        revealed
+    }
+
+    pub fn explore_all(&mut self) {
+        for cell in self.cells.iter_mut() {
+            cell.explored = true;
+        }
+    }
+
+    // Unexplored tiles on the edge of what has been explored, each listed once.
+    pub fn scout_targets(&self, layout: GridLayout) -> Vec<GridCoord> {
+        self.coords()
+            .filter(|c| !self.get(*c).unwrap().explored)
+            .filter(|c| {
+                layout
+                    .neighbours(*c)
+                    .into_iter()
+                    .any(|n| self.get(n).map(|t| t.explored).unwrap_or(false))
+            })
+            .collect()
+    }
+
+    // Buildable tiles next to at least one connected building, each listed once.
+    pub fn available_for_building(&self, layout: GridLayout) -> Vec<GridCoord> {
+        let mut seen = HashSet::new();
+        let mut available = Vec::new();
+        for (coord, _) in self.buildings() {
+            if !self.get(coord).unwrap().connected {
+                continue;
+            }
+            for neighbour in layout.neighbours(coord) {
+                let buildable = self.get(neighbour).map(|t| t.is_buildable());
+                if buildable == Some(true) && seen.insert(neighbour) {
+                    available.push(neighbour);
+                }
+            }
+        }
+        available
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A plains map with the start tile on `start` and a building on each of `buildings`.
+    fn with_buildings(
+        width: i32,
+        height: i32,
+        start: GridCoord,
+        buildings: &[GridCoord],
+    ) -> MapGrid {
+        let mut grid = MapGrid::new(width, height);
+        grid.get_mut(start).unwrap().start_tile = true;
+        for coord in buildings {
+            grid.get_mut(*coord).unwrap().building = Some(BuildingType::Conduit);
+        }
+        grid
+    }
+
+    fn connected(grid: &MapGrid) -> Vec<GridCoord> {
+        grid.coords()
+            .filter(|c| grid.get(*c).unwrap().connected)
+            .collect()
+    }
+
+    #[test]
+    fn connectivity_follows_neighbouring_buildings() {
+        let c = GridCoord::new;
+        let mut grid = with_buildings(4, 2, c(0, 0), &[c(0, 0), c(1, 0), c(1, 1), c(3, 0)]);
+
+        let changed = grid.update_connectivity(GridLayout::Square);
+        assert_eq!(changed, vec![c(0, 0), c(1, 0), c(1, 1)]);
+        assert_eq!(connected(&grid), changed);
+
+        // Only tiles that changed are reported the next time.
+        grid.get_mut(c(2, 0)).unwrap().building = Some(BuildingType::Conduit);
+        let changed = grid.update_connectivity(GridLayout::Square);
+        assert_eq!(changed, vec![c(2, 0), c(3, 0)]);
+
+        grid.get_mut(c(1, 0)).unwrap().building = None;
+        let changed = grid.update_connectivity(GridLayout::Square);
+        assert_eq!(changed, vec![c(1, 0), c(2, 0), c(3, 0), c(1, 1)]);
+        assert_eq!(connected(&grid), vec![c(0, 0)]);
+    }
+
+    #[test]
+    fn nothing_connects_without_a_base() {
+        let c = GridCoord::new;
+        let mut grid = with_buildings(3, 1, c(0, 0), &[c(1, 0), c(2, 0)]);
+        assert!(grid.update_connectivity(GridLayout::Square).is_empty());
+    }
+
+    #[test]
+    fn connectivity_uses_the_layout() {
+        // (1, 0) touches (0, 1) on a pointy hex map, but not on a square one.
+        let c = GridCoord::new;
+        let mut grid = with_buildings(2, 2, c(0, 1), &[c(0, 1), c(1, 0)]);
+        grid.update_connectivity(GridLayout::Square);
+        assert_eq!(connected(&grid), vec![c(0, 1)]);
+        grid.update_connectivity(GridLayout::PointyHex);
+        assert_eq!(connected(&grid), vec![c(1, 0), c(0, 1)]);
+    }
+
+    #[test]
+    fn explore_reveals_a_radius_once() {
+        let c = GridCoord::new;
+        let mut grid = MapGrid::new(5, 5);
+
+        let revealed = grid.explore(c(2, 2), 1, GridLayout::Square);
+        assert_eq!(revealed, vec![c(2, 1), c(1, 2), c(2, 2), c(3, 2), c(2, 3)]);
+        assert!(grid.explore(c(2, 2), 1, GridLayout::Square).is_empty());
+
+        // The overlap with what is already explored isn't reported again.
+        let revealed = grid.explore(c(3, 2), 1, GridLayout::Square);
+        assert_eq!(revealed, vec![c(3, 1), c(4, 2), c(3, 3)]);
+    }
+
+    #[test]
+    fn scout_targets_border_the_explored_tiles() {
+        let c = GridCoord::new;
+        let mut grid = MapGrid::new(3, 3);
+        assert!(grid.scout_targets(GridLayout::Square).is_empty());
+
+        grid.explore(c(0, 0), 0, GridLayout::Square);
+        assert_eq!(
+            grid.scout_targets(GridLayout::Square),
+            vec![c(1, 0), c(0, 1)]
+        );
+
+        grid.explore_all();
+        assert!(grid.scout_targets(GridLayout::Square).is_empty());
+        assert!(grid.coords().all(|c| grid.get(c).unwrap().explored));
+    }
+
+    #[test]
+    fn only_explored_empty_tiles_are_buildable() {
+        let c = GridCoord::new;
+        let mut grid = with_buildings(3, 1, c(0, 0), &[c(0, 0)]);
+        grid.update_connectivity(GridLayout::Square);
+        assert!(grid.available_for_building(GridLayout::Square).is_empty());
+
+        grid.explore(c(0, 0), 1, GridLayout::Square);
+        assert_eq!(
+            grid.available_for_building(GridLayout::Square),
+            vec![c(1, 0)]
+        );
+        assert!(!grid.get(c(0, 0)).unwrap().is_buildable());
+    }
+
+    #[test]
+    fn connected_by_lists_what_a_building_would_link_up() {
+        let c = GridCoord::new;
+        let mut grid = with_buildings(5, 1, c(0, 0), &[c(0, 0), c(2, 0), c(3, 0)]);
+        grid.update_connectivity(GridLayout::Square);
+
+        assert_eq!(
+            grid.connected_by(c(1, 0), GridLayout::Square),
+            vec![c(1, 0), c(2, 0), c(3, 0)]
+        );
+        // (4, 0) only touches buildings that aren't connected themselves.
+        assert!(grid.connected_by(c(4, 0), GridLayout::Square).is_empty());
+    }
+}
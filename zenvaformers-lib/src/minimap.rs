@@ -0,0 +1,261 @@
+use gdnative::{
+    api::{
+        Camera2D, GlobalConstants, Image, ImageTexture, InputEventMouseButton,
+        InputEventMouseMotion,
+    },
+    prelude::*,
+};
+
+use crate::map_grid::TileState;
+use crate::Map;
+
+const BACKGROUND: Color = Color {
+    r: 0.08,
+    g: 0.08,
+    b: 0.1,
+    a: 1.0,
+};
+const CAMERA_RECT: Color = Color {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 0.9,
+};
+
+// The whole map drawn into a small image, each tile filling its own outline, with the
+// camera's view on top. Clicking or dragging on it moves the camera there.
+#[derive(NativeClass)]
+#[inherit(TextureRect)]
+pub struct Minimap {
+    image: Option<Ref<Image, Shared>>,
+    texture: Option<Ref<ImageTexture, Shared>>,
+    // Minimap pixels per world pixel, and where the map's top left corner lands.
+    scale: f32,
+    offset: Vector2,
+    bounds: Rect2,
+    // Last camera rectangle drawn, so the overlay is only redrawn when it moves.
+    camera_rect: Rect2,
+}
+
+#[methods]
+impl Minimap {
+    fn new(_base: &TextureRect) -> Self {
+        Minimap {
+            image: None,
+            texture: None,
+            scale: 1.0,
+            offset: Vector2::ZERO,
+            bounds: Rect2::new(Vector2::ZERO, Vector2::ZERO),
+            camera_rect: Rect2::new(Vector2::ZERO, Vector2::ZERO),
+        }
+    }
+
+    fn get_map(base: &TextureRect) -> TInstance<'static, Map> {
+        unsafe {
+            base.get_node_as_instance::<Map>("/root/MainScene/Tiles")
+                .unwrap()
+        }
+    }
+
+    fn get_camera(base: &TextureRect) -> TRef<'static, Camera2D> {
+        unsafe {
+            base.get_node_as::<Camera2D>("/root/MainScene/Camera")
+                .unwrap()
+        }
+    }
+
+    #[method]
+    fn _ready(&mut self, #[base] base: &TextureRect) {
+        self.rebuild(base);
+    }
+
+    // Draws every tile from scratch. Afterwards only tiles the Map reports as changed are.
+    #[method]
+    fn rebuild(&mut self, #[base] base: &TextureRect) {
+        let size = base.size();
+        let map = Self::get_map(base);
+        self.bounds = map.map(|m, map_base| m.world_bounds(&map_base)).unwrap();
+        if self.bounds.size.x <= 0.0 || self.bounds.size.y <= 0.0 {
+            return;
+        }
+        self.scale = (size.x / self.bounds.size.x).min(size.y / self.bounds.size.y);
+        self.offset = (size - self.bounds.size * self.scale) / 2.0;
+
+        let image = Image::new();
+        image.create(size.x as i64, size.y as i64, false, Image::FORMAT_RGBA8);
+        image.fill(BACKGROUND);
+        let image = image.into_shared();
+        self.image = Some(image);
+
+        map.map(|m, map_base| {
+            // Anything queued up so far is covered by drawing everything.
+            m.take_changed();
+            let outline = m.layout().outline(m.tile_size());
+            for coord in m.grid().coords() {
+                let position = m.grid_to_world(&map_base, coord);
+                self.paint(position, &outline, &m.visible_state(coord).unwrap());
+            }
+        })
+        .unwrap();
+
+        let texture = ImageTexture::new();
+        texture.create_from_image(self.image.as_ref().unwrap(), 0);
+        let texture = texture.into_shared();
+        base.set_texture(texture.clone());
+        self.texture = Some(texture);
+    }
+
+    fn color_for(state: &TileState) -> Color {
+        if !state.explored {
+            BACKGROUND
+        } else if state.building.is_some() && state.connected {
+            Color::from_rgb(0.95, 0.95, 0.95)
+        } else if state.building.is_some() {
+            Color::from_rgb(0.55, 0.55, 0.55)
+        } else {
+            state.terrain.get_color()
+        }
+    }
+
+    // Fills the pixels whose centres fall inside the tile's outline, so hex and isometric
+    // tiles leave their neighbours alone. The image must not be locked.
+    fn paint(&self, position: Vector2, outline: &[Vector2], state: &TileState) {
+        let image = match &self.image {
+            Some(image) => unsafe { image.assume_safe() },
+            None => return,
+        };
+        let color = Self::color_for(state);
+        let (mut from, mut to) = (position, position);
+        for point in outline.iter() {
+            from = Vector2::new(
+                from.x.min(position.x + point.x),
+                from.y.min(position.y + point.y),
+            );
+            to = Vector2::new(
+                to.x.max(position.x + point.x),
+                to.y.max(position.y + point.y),
+            );
+        }
+        let (from, to) = (self.to_minimap(from), self.to_minimap(to));
+        let (width, height) = (image.get_width(), image.get_height());
+
+        image.lock();
+        for y in (from.y as i64).max(0)..(to.y.ceil() as i64).min(height) {
+            for x in (from.x as i64).max(0)..(to.x.ceil() as i64).min(width) {
+                let centre = self.to_world(Vector2::new(x as f32 + 0.5, y as f32 + 0.5));
+                if contains(outline, centre - position) {
+                    image.set_pixel(x, y, color);
+                }
+            }
+        }
+        image.unlock();
+    }
+
+    fn to_minimap(&self, world: Vector2) -> Vector2 {
+        (world - self.bounds.position) * self.scale + self.offset
+    }
+
+    fn to_world(&self, local: Vector2) -> Vector2 {
+        (local - self.offset) / self.scale + self.bounds.position
+    }
+
+    #[method]
+    fn _process(&mut self, #[base] base: &TextureRect, _delta: f64) {
+        let changed = Self::get_map(base)
+            .map(|m, map_base| {
+                m.take_changed()
+                    .into_iter()
+                    .map(|coord| {
+                        (
+                            m.grid_to_world(&map_base, coord),
+                            m.visible_state(coord).unwrap(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap();
+        if !changed.is_empty() {
+            let outline = Self::get_map(base)
+                .map(|m, _| m.layout().outline(m.tile_size()))
+                .unwrap();
+            for (position, state) in changed.iter() {
+                self.paint(*position, &outline, state);
+            }
+            // One upload a frame, however many tiles changed.
+            if let (Some(texture), Some(image)) = (&self.texture, &self.image) {
+                unsafe { texture.assume_safe() }.set_data(image);
+            }
+        }
+
+        let camera_rect = self.camera_rect(base);
+        if camera_rect != self.camera_rect {
+            self.camera_rect = camera_rect;
+            base.update();
+        }
+    }
+
+    // The part of the world the camera shows, in minimap pixels and cut to the minimap.
+    fn camera_rect(&self, base: &TextureRect) -> Rect2 {
+        let camera = Self::get_camera(base);
+        let viewport_size = match camera.get_viewport() {
+            Some(viewport) => unsafe { viewport.assume_safe() }.get_visible_rect().size,
+            None => return self.camera_rect,
+        };
+        let view = viewport_size * camera.zoom().x;
+        let top_left = self.to_minimap(camera.get_camera_screen_center() - view / 2.0);
+        let bottom_right = top_left + view * self.scale;
+
+        let size = base.size();
+        let from = Vector2::new(top_left.x.max(0.0), top_left.y.max(0.0));
+        let to = Vector2::new(bottom_right.x.min(size.x), bottom_right.y.min(size.y));
+        Rect2::new(
+            from,
+            Vector2::new((to.x - from.x).max(0.0), (to.y - from.y).max(0.0)),
+        )
+    }
+
+    #[method]
+    fn _draw(&self, #[base] base: &TextureRect) {
+        if self.camera_rect.size.x > 0.0 && self.camera_rect.size.y > 0.0 {
+            base.draw_rect(self.camera_rect, CAMERA_RECT, false, 1.0, false);
+        }
+    }
+
+    #[method]
+    fn _gui_input(&self, #[base] base: &TextureRect, event: Ref<InputEvent>) {
+        let event = unsafe { event.assume_safe() };
+        let position = if let Some(button) = event.cast::<InputEventMouseButton>() {
+            if button.button_index() != GlobalConstants::BUTTON_LEFT || !button.is_pressed() {
+                return;
+            }
+            button.position()
+        } else if let Some(motion) = event.cast::<InputEventMouseMotion>() {
+            if motion.button_mask() & GlobalConstants::BUTTON_MASK_LEFT == 0 {
+                return;
+            }
+            motion.position()
+        } else {
+            return;
+        };
+
+        let camera = Self::get_camera(base);
+        unsafe { camera.call("focus_on", &[self.to_world(position).to_variant()]) };
+        base.accept_event();
+    }
+}
+
+// Whether a point is inside a convex polygon, edges included, whichever way it winds.
+fn contains(polygon: &[Vector2], point: Vector2) -> bool {
+    let mut sign = 0.0;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let cross = (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x);
+        if cross != 0.0 {
+            if cross * sign < 0.0 {
+                return false;
+            }
+            sign = cross;
+        }
+    }
+    !polygon.is_empty()
+}
@@ -0,0 +1,63 @@
+// Seeded value noise, used by Map to lay out terrain and deposits.
+// Nothing fancy: hashed lattice values, smoothed and summed over a few octaves.
+pub struct ValueNoise {
+    seed: u64,
+}
+
+impl ValueNoise {
+    pub fn new(seed: i64) -> Self {
+        ValueNoise { seed: seed as u64 }
+    }
+
+    // Pseudo random value in 0.0..=1.0 for a lattice point.
+    fn lattice(&self, x: i32, y: i32) -> f32 {
+        let mut h = self
+            .seed
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            .wrapping_add((x as u64).wrapping_mul(0x0000_0001_6581_2A6D))
+            .wrapping_add((y as u64).wrapping_mul(0x0000_0000_27D4_EB2F));
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+        h ^= h >> 33;
+        (h & 0xFFFF) as f32 / 65535.0
+    }
+
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let (ix, iy) = (x0 as i32, y0 as i32);
+        let tx = smooth(x - x0);
+        let ty = smooth(y - y0);
+
+        let top = lerp(self.lattice(ix, iy), self.lattice(ix + 1, iy), tx);
+        let bottom = lerp(self.lattice(ix, iy + 1), self.lattice(ix + 1, iy + 1), tx);
+        lerp(top, bottom, ty)
+    }
+
+    // Sums octaves of decreasing amplitude, normalised back to 0.0..=1.0.
+    pub fn fractal(&self, x: f32, y: f32, octaves: u32) -> f32 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut max = 0.0;
+        for _ in 0..octaves {
+            total += self.sample(x * frequency, y * frequency) * amplitude;
+            max += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        if max > 0.0 {
+            total / max
+        } else {
+            0.0
+        }
+    }
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
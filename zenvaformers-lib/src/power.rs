@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use gdnative::prelude::*;
+
+use crate::grid::{GridCoord, GridLayout};
+use crate::map_grid::MapGrid;
+use crate::{Building, BuildingType, ResourceType};
+
+// Fraction of a producer's output lost for every tile beyond the first that it travels
+// to reach a consumer. Neighbours share power for free.
+const LOSS_PER_STEP: f32 = 0.05;
+
+// One power grid: buildings linked through their neighbours. The Base ties supply lines
+// together but doesn't carry power, so every branch leaving it is a grid of its own.
+#[derive(Clone)]
+pub struct PowerGrid {
+    pub tiles: Vec<GridCoord>,
+    // Power that arrives at consumers after transmission loss.
+    pub supply: f32,
+    pub demand: f32,
+    // Supply left once the powered consumers are served. It goes into storage.
+    pub spare: f32,
+    // Consumers that got all the power they need this turn.
+    pub powered: Vec<GridCoord>,
+}
+
+impl PowerGrid {
+    // Negative when some consumers go without.
+    pub fn surplus(&self) -> f32 {
+        self.supply - self.demand
+    }
+
+    // A distinct colour per grid for the overlay.
+    pub fn color(index: usize) -> Color {
+        let hue = (index as f32 * 0.61803).fract();
+        Color::from_hsv(hue, 0.6, 1.0)
+    }
+}
+
+// Splits the operational buildings into grids and shares each grid's power out,
+// nearest consumers first. `data` looks up what a building type produces and uses.
+pub fn simulate(
+    grid: &MapGrid,
+    layout: GridLayout,
+    data: &dyn Fn(&BuildingType) -> Building,
+) -> Vec<PowerGrid> {
+    let carries_power = |coord: GridCoord| {
+        grid.get(coord)
+            .map(|t| t.connected && matches!(&t.building, Some(b) if *b != BuildingType::Base))
+            .unwrap_or(false)
+    };
+
+    let mut seen = HashSet::new();
+    let mut grids = Vec::new();
+    for start in grid.coords() {
+        if !carries_power(start) || seen.contains(&start) {
+            continue;
+        }
+        let tiles = flood(start, layout, &carries_power);
+        seen.extend(tiles.iter().cloned());
+        grids.push(share_power(grid, layout, tiles, data, &carries_power));
+    }
+    grids
+}
+
+fn flood(
+    start: GridCoord,
+    layout: GridLayout,
+    carries_power: &dyn Fn(GridCoord) -> bool,
+) -> Vec<GridCoord> {
+    distances_from(&[start], layout, carries_power)
+        .into_keys()
+        .collect()
+}
+
+// Steps from the nearest of `sources` to every tile reachable through the grid.
+fn distances_from(
+    sources: &[GridCoord],
+    layout: GridLayout,
+    carries_power: &dyn Fn(GridCoord) -> bool,
+) -> HashMap<GridCoord, i32> {
+    let mut distances: HashMap<GridCoord, i32> = sources.iter().map(|c| (*c, 0)).collect();
+    let mut queue: VecDeque<GridCoord> = sources.iter().cloned().collect();
+    while let Some(coord) = queue.pop_front() {
+        let distance = distances[&coord];
+        for neighbour in layout.neighbours(coord) {
+            if carries_power(neighbour) && !distances.contains_key(&neighbour) {
+                distances.insert(neighbour, distance + 1);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+    distances
+}
+
+fn share_power(
+    grid: &MapGrid,
+    layout: GridLayout,
+    tiles: Vec<GridCoord>,
+    data: &dyn Fn(&BuildingType) -> Building,
+    carries_power: &dyn Fn(GridCoord) -> bool,
+) -> PowerGrid {
+    let mut producers = Vec::new();
+    let mut consumers = Vec::new();
+    for coord in tiles.iter() {
+        let b = data(grid.get(*coord).unwrap().building.as_ref().unwrap());
+        if b.resource_type == ResourceType::Energy {
+            producers.push((*coord, b.resource_amount as f32));
+        }
+        if b.upkeep_type == ResourceType::Energy {
+            consumers.push((*coord, b.upkeep_amount as f32));
+        }
+    }
+
+    let producer_coords: Vec<GridCoord> = producers.iter().map(|(c, _)| *c).collect();
+    let consumer_coords: Vec<GridCoord> = consumers.iter().map(|(c, _)| *c).collect();
+    let to_consumers = distances_from(&consumer_coords, layout, carries_power);
+    let to_producers = distances_from(&producer_coords, layout, carries_power);
+
+    // Each producer loses power over the distance to its nearest consumer.
+    let supply: f32 = producers
+        .iter()
+        .map(|(coord, output)| {
+            let steps = (to_consumers.get(coord).cloned().unwrap_or(0) - 1).max(0) as f32;
+            output * (1.0 - LOSS_PER_STEP * steps).max(0.0)
+        })
+        .sum();
+
+    consumers.sort_by_key(|(coord, _)| to_producers.get(coord).cloned().unwrap_or(i32::MAX));
+    let mut remaining = supply;
+    let mut powered = Vec::new();
+    for (coord, demand) in consumers.iter() {
+        if *demand <= remaining {
+            remaining -= demand;
+            powered.push(*coord);
+        }
+    }
+
+    PowerGrid {
+        tiles,
+        supply,
+        demand: consumers.iter().map(|(_, demand)| demand).sum(),
+        spare: remaining,
+        powered,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(building_type: &BuildingType) -> Building {
+        match building_type {
+            BuildingType::Mine => Building::mine(),
+            BuildingType::SolarPanel => Building::solar_panel(),
+            BuildingType::Conduit => Building::conduit(),
+            _ => Building::base(),
+        }
+    }
+
+    // A row of tiles, the Base on `base` and nothing where `buildings` has None.
+    fn row(base: usize, buildings: &[Option<BuildingType>]) -> MapGrid {
+        let mut grid = MapGrid::new(buildings.len() as i32, 1);
+        for (x, building) in buildings.iter().enumerate() {
+            let state = grid.get_mut(GridCoord::new(x as i32, 0)).unwrap();
+            state.building = building.clone();
+            state.start_tile = x == base;
+        }
+        grid.update_connectivity(GridLayout::Square);
+        grid
+    }
+
+    fn at(x: i32) -> GridCoord {
+        GridCoord::new(x, 0)
+    }
+
+    #[test]
+    fn base_splits_grids() {
+        let grid = row(
+            1,
+            &[
+                Some(BuildingType::SolarPanel),
+                Some(BuildingType::Base),
+                Some(BuildingType::Mine),
+            ],
+        );
+        let grids = simulate(&grid, GridLayout::Square, &data);
+        assert_eq!(grids.len(), 2);
+
+        assert_eq!(grids[0].tiles, vec![at(0)]);
+        assert_eq!(
+            (grids[0].supply, grids[0].demand, grids[0].spare),
+            (1.0, 0.0, 1.0)
+        );
+        assert_eq!(grids[1].tiles, vec![at(2)]);
+        assert_eq!(grids[1].surplus(), -1.0);
+        assert!(grids[1].powered.is_empty());
+    }
+
+    #[test]
+    fn unconnected_buildings_carry_no_power() {
+        let grid = row(
+            0,
+            &[
+                Some(BuildingType::Base),
+                None,
+                Some(BuildingType::SolarPanel),
+                Some(BuildingType::Mine),
+            ],
+        );
+        assert!(simulate(&grid, GridLayout::Square, &data).is_empty());
+    }
+
+    #[test]
+    fn power_is_lost_along_conduits() {
+        let grid = row(
+            0,
+            &[
+                Some(BuildingType::Base),
+                Some(BuildingType::SolarPanel),
+                Some(BuildingType::Conduit),
+                Some(BuildingType::Conduit),
+                Some(BuildingType::Mine),
+            ],
+        );
+        let grids = simulate(&grid, GridLayout::Square, &data);
+        assert_eq!(grids.len(), 1);
+
+        // Two steps beyond the first, so 10% of the panel's output is lost.
+        assert!((grids[0].supply - 0.9).abs() < 1e-6);
+        assert!(grids[0].powered.is_empty());
+        assert!((grids[0].spare - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn nearest_consumers_are_powered_first() {
+        let grid = row(
+            0,
+            &[
+                Some(BuildingType::Base),
+                Some(BuildingType::Mine),
+                Some(BuildingType::Conduit),
+                Some(BuildingType::SolarPanel),
+                Some(BuildingType::Mine),
+            ],
+        );
+        let grids = simulate(&grid, GridLayout::Square, &data);
+        assert_eq!(grids.len(), 1);
+
+        assert_eq!((grids[0].supply, grids[0].demand), (1.0, 2.0));
+        assert_eq!(grids[0].powered, vec![at(4)]);
+        assert_eq!(grids[0].spare, 0.0);
+    }
+}
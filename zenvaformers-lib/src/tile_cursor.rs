@@ -0,0 +1,168 @@
+use gdnative::{api::InputEventMouseMotion, prelude::*};
+
+use crate::grid::GridCoord;
+use crate::Map;
+
+const CURSOR_COLOR: Color = Color {
+    r: 1.0,
+    g: 0.9,
+    b: 0.3,
+    a: 1.0,
+};
+// How closely a neighbour has to line up with the pressed direction to be moved to.
+const MIN_ALIGNMENT: f32 = 0.5;
+
+// A tile outline moved with the cursor_* actions (arrow keys, D-pad) and confirmed with
+// cursor_confirm, so the map can be played without a mouse. It acts on tiles exactly as
+// hovering and clicking them does. Moving the mouse hides it again.
+#[derive(NativeClass)]
+#[inherit(Node2D)]
+pub struct TileCursor {
+    coord: Option<GridCoord>,
+}
+
+#[methods]
+impl TileCursor {
+    fn new(_base: &Node2D) -> Self {
+        TileCursor { coord: None }
+    }
+
+    fn get_map(base: &Node2D) -> TInstance<'static, Map> {
+        unsafe {
+            base.get_node_as_instance::<Map>("/root/MainScene/Tiles")
+                .unwrap()
+        }
+    }
+
+    fn get_game_manager(base: &Node2D) -> TRef<'static, Node> {
+        unsafe { base.get_node_as::<Node>("/root/MainScene").unwrap() }
+    }
+
+    fn get_ui(base: &Node2D) -> TRef<'static, Node> {
+        unsafe { base.get_node_as::<Node>("/root/MainScene/HUD/UI").unwrap() }
+    }
+
+    #[method]
+    fn _ready(&self, #[base] base: &Node2D) {
+        base.set_visible(false);
+    }
+
+    #[method]
+    fn _unhandled_input(&mut self, #[base] base: &Node2D, event: Ref<InputEvent>) {
+        let event = unsafe { event.assume_safe() };
+        if event.cast::<InputEventMouseMotion>().is_some() {
+            self.hide(base);
+            return;
+        }
+
+        let pressed = |action: &str| event.is_action_pressed(action, true, false);
+        let mut direction = Vector2::ZERO;
+        if pressed("cursor_up") {
+            direction.y -= 1.0;
+        }
+        if pressed("cursor_down") {
+            direction.y += 1.0;
+        }
+        if pressed("cursor_left") {
+            direction.x -= 1.0;
+        }
+        if pressed("cursor_right") {
+            direction.x += 1.0;
+        }
+
+        if direction != Vector2::ZERO {
+            self.step(base, direction);
+        } else if pressed("cursor_confirm") && base.is_visible() {
+            if let Some(coord) = self.coord {
+                let game_manager = Self::get_game_manager(base);
+                // Deferred, like a Tile click.
+                unsafe { game_manager.call_deferred("on_tile_clicked", &[coord.to_variant()]) };
+            }
+        } else {
+            return;
+        }
+        if let Some(viewport) = base.get_viewport() {
+            unsafe { viewport.assume_safe() }.set_input_as_handled();
+        }
+    }
+
+    // The first press only brings the cursor back, where it was or on the start tile.
+    fn step(&mut self, base: &Node2D, direction: Vector2) {
+        let map = Self::get_map(base);
+        let target = match (self.coord, base.is_visible()) {
+            (Some(coord), true) => map
+                .map(|m, map_base| {
+                    let from = m.grid_to_world(&map_base, coord);
+                    m.neighbours(&map_base, coord)
+                        .into_iter()
+                        .map(|n| {
+                            let towards = (m.grid_to_world(&map_base, n) - from).normalized();
+                            (n, towards.dot(direction.normalized()))
+                        })
+                        .filter(|(_, alignment)| *alignment >= MIN_ALIGNMENT)
+                        .max_by(|a, b| a.1.total_cmp(&b.1))
+                        .map(|(n, _)| n)
+                })
+                .unwrap(),
+            (Some(coord), false) => Some(coord),
+            (None, _) => map.map(|m, _| m.grid().start_tile()).unwrap(),
+        };
+        if let Some(target) = target {
+            self.move_to(base, target);
+        }
+    }
+
+    fn move_to(&mut self, base: &Node2D, coord: GridCoord) {
+        if base.is_visible() {
+            if let Some(old) = self.coord {
+                self.unhover(base, old);
+            }
+        }
+        self.coord = Some(coord);
+        let position = Self::get_map(base)
+            .map(|m, map_base| m.grid_to_world(&map_base, coord))
+            .unwrap();
+        base.set_position(position);
+        base.set_visible(true);
+        base.update();
+        self.hover(base, coord);
+
+        let camera = unsafe { base.get_node_as::<Node>("/root/MainScene/Camera").unwrap() };
+        unsafe { camera.call("ensure_visible", &[position.to_variant()]) };
+    }
+
+    fn hide(&self, base: &Node2D) {
+        if !base.is_visible() {
+            return;
+        }
+        base.set_visible(false);
+        if let Some(coord) = self.coord {
+            self.unhover(base, coord);
+        }
+    }
+
+    fn hover(&self, base: &Node2D, coord: GridCoord) {
+        let coord = coord.to_variant();
+        let game_manager = Self::get_game_manager(base);
+        unsafe { game_manager.call_deferred("on_tile_hovered", &[coord.clone()]) };
+        unsafe { Self::get_ui(base).call_deferred("show_tile_tooltip", &[coord]) };
+    }
+
+    fn unhover(&self, base: &Node2D, coord: GridCoord) {
+        let coord = coord.to_variant();
+        let game_manager = Self::get_game_manager(base);
+        unsafe { game_manager.call_deferred("on_tile_unhovered", &[coord.clone()]) };
+        unsafe { Self::get_ui(base).call_deferred("hide_tile_tooltip", &[coord]) };
+    }
+
+    #[method]
+    fn _draw(&self, #[base] base: &Node2D) {
+        let mut outline = Self::get_map(base)
+            .map(|m, _| m.layout().outline(m.tile_size()))
+            .unwrap();
+        if let Some(first) = outline.first().cloned() {
+            outline.push(first);
+        }
+        base.draw_polyline(PoolArray::from_vec(outline), CURSOR_COLOR, 3.0, true);
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+
+use gdnative::{
+    api::{PackedScene, OS},
+    prelude::*,
+};
+
+use crate::map_grid::MapGrid;
+use crate::Map;
+
+// Scene checked by SceneValidator when it isn't given one on the command line.
+const DEFAULT_SCENE: &str = "res://MainScene.tscn";
+// Where the Map lives inside that scene, matching GameManager::get_map.
+const MAP_NODE: &str = "Tiles";
+
+// One problem found in a scene: which node (or tile) it is on and what is wrong with it.
+pub struct Diagnostic {
+    pub path: String,
+    pub reason: String,
+}
+
+impl Diagnostic {
+    pub fn new(path: impl Into<String>, reason: impl Into<String>) -> Self {
+        Diagnostic {
+            path: path.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
+    }
+}
+
+// Checks the map data itself. Tiles are reported as "<map path> (x, y)".
+pub fn validate_grid(grid: &MapGrid, map_path: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let tile_path = |x: i32, y: i32| format!("{} ({}, {})", map_path, x, y);
+
+    let starts: Vec<_> = grid
+        .coords()
+        .filter(|c| grid.get(*c).unwrap().start_tile)
+        .collect();
+    match starts.len() {
+        0 => diagnostics.push(Diagnostic::new(map_path, "has no start tile")),
+        1 => {}
+        count => {
+            for coord in starts.iter() {
+                diagnostics.push(Diagnostic::new(
+                    tile_path(coord.x, coord.y),
+                    format!("is one of {} start tiles, there must be exactly one", count),
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+pub fn report(map_path: &str, diagnostics: &[Diagnostic]) {
+    if diagnostics.is_empty() {
+        godot_print!("{}: no problems found", map_path);
+        return;
+    }
+    let lines: Vec<String> = diagnostics.iter().map(|d| format!("  {}", d)).collect();
+    godot_error!(
+        "{}: {} problem(s) found\n{}",
+        map_path,
+        diagnostics.len(),
+        lines.join("\n")
+    );
+}
+
+// Validates a scene without opening the game window:
+//   godot --no-window -s res://SceneValidator.gdns [res://Other.tscn]
+// Exits with 1 when anything is wrong, so it can run in CI.
+#[derive(NativeClass)]
+#[inherit(SceneTree)]
+pub struct SceneValidator {}
+
+#[methods]
+impl SceneValidator {
+    fn new(_base: &SceneTree) -> Self {
+        SceneValidator {}
+    }
+
+    #[method]
+    fn _initialize(&self, #[base] base: &SceneTree) {
+        let scene_path = OS::godot_singleton()
+            .get_cmdline_args()
+            .read()
+            .iter()
+            .map(|a| a.to_string())
+            .find(|a| a.ends_with(".tscn"))
+            .unwrap_or_else(|| DEFAULT_SCENE.to_owned());
+
+        let problems = match Self::validate_scene(base, &scene_path) {
+            Ok(problems) => problems,
+            Err(e) => {
+                godot_error!("{}", e);
+                1
+            }
+        };
+        base.quit(if problems == 0 { 0 } else { 1 });
+    }
+
+    // Returns the number of problems found.
+    fn validate_scene(base: &SceneTree, scene_path: &str) -> Result<usize, String> {
+        let scene = load::<PackedScene>(scene_path)
+            .ok_or_else(|| format!("{}: could not load scene", scene_path))?;
+        let scene = unsafe { scene.assume_safe() }
+            .instance(PackedScene::GEN_EDIT_STATE_DISABLED)
+            .ok_or_else(|| format!("{}: could not instance scene", scene_path))?;
+        let scene = unsafe { scene.assume_safe() };
+
+        // Entering the tree runs Map::_ready, which builds the tiles the checks look at.
+        let root = base
+            .root()
+            .ok_or_else(|| "SceneTree has no root".to_owned())?;
+        unsafe { root.assume_safe() }.add_child(scene, false);
+
+        let map = scene
+            .get_node(MAP_NODE)
+            .map(|n| unsafe { n.assume_safe() })
+            .and_then(|n| n.cast_instance::<Map>())
+            .ok_or_else(|| format!("{}/{}: is missing or is not a Map", scene_path, MAP_NODE))?;
+        let problems = map
+            .map(|m, map_base| m.diagnostics(&map_base).len())
+            .unwrap();
+
+        scene.queue_free();
+        Ok(problems)
+    }
+}
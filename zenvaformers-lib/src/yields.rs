@@ -0,0 +1,374 @@
+use std::collections::HashSet;
+
+use gdnative::prelude::*;
+
+use crate::grid::{GridCoord, GridLayout};
+use crate::map_grid::{MapGrid, TileState};
+use crate::power::{self, PowerGrid};
+use crate::{Building, BuildingType, ResourceAmounts, ResourceType};
+
+// What one building makes and costs this turn, after everything that changes the
+// BuildingData numbers. Inactive buildings make and cost nothing.
+#[derive(Clone, ToVariant, FromVariant)]
+pub struct BuildingYield {
+    pub coord: GridCoord,
+    pub building_type: BuildingType,
+    pub production_type: ResourceType,
+    pub production: i32,
+    pub upkeep_type: ResourceType,
+    pub upkeep: i32,
+    pub active: bool,
+    // Why the numbers differ from BuildingData, or why the building is inactive.
+    pub modifiers: Vec<YieldModifier>,
+}
+
+impl BuildingYield {
+    // What this building adds to each resource this turn, upkeep as a negative amount.
+    pub fn amounts(&self) -> [(ResourceType, i32); 2] {
+        let active = if self.active { 1 } else { 0 };
+        [
+            (self.production_type.clone(), self.production * active),
+            (self.upkeep_type.clone(), -self.upkeep * active),
+        ]
+    }
+}
+
+// Kept as what happened rather than as text, so yields worked out before a language switch
+// still show in the new language.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum YieldModifier {
+    NotConnected = 0,
+    PoweredByGrid = 1,
+    NotEnoughPower = 2,
+    FeedsGrid = 3,
+}
+
+impl YieldModifier {
+    // English, to be passed through tr.
+    pub fn text(&self) -> &'static str {
+        match self {
+            YieldModifier::NotConnected => "Not connected to the Base",
+            YieldModifier::PoweredByGrid => "Powered by its grid",
+            YieldModifier::NotEnoughPower => "Not enough power on its grid",
+            YieldModifier::FeedsGrid => "Feeds its power grid",
+        }
+    }
+}
+
+impl ToVariant for YieldModifier {
+    fn to_variant(&self) -> Variant {
+        match self {
+            YieldModifier::NotConnected => 0.to_variant(),
+            YieldModifier::PoweredByGrid => 1.to_variant(),
+            YieldModifier::NotEnoughPower => 2.to_variant(),
+            YieldModifier::FeedsGrid => 3.to_variant(),
+        }
+    }
+}
+
+impl FromVariant for YieldModifier {
+    fn from_variant(variant: &Variant) -> Result<Self, FromVariantError> {
+        let result = i64::from_variant(variant)?;
+        match result {
+            0 => Ok(YieldModifier::NotConnected),
+            1 => Ok(YieldModifier::PoweredByGrid),
+            2 => Ok(YieldModifier::NotEnoughPower),
+            3 => Ok(YieldModifier::FeedsGrid),
+            _ => Err(FromVariantError::UnknownEnumVariant {
+                variant: "i64".to_owned(),
+                expected: &["0", "1", "2", "3"],
+            }),
+        }
+    }
+}
+
+// What every building on the map makes and costs this turn, plus each power grid when
+// `power_grid` is on. `data` looks up what a building type produces and uses.
+pub fn compute(
+    grid: &MapGrid,
+    layout: GridLayout,
+    power_grid: bool,
+    data: &dyn Fn(&BuildingType) -> Building,
+) -> (Vec<BuildingYield>, Vec<PowerGrid>) {
+    let grids = if power_grid {
+        power::simulate(grid, layout, data)
+    } else {
+        Vec::new()
+    };
+    let powered: HashSet<GridCoord> = grids
+        .iter()
+        .flat_map(|g| g.powered.iter().cloned())
+        .collect();
+
+    let yields = grid
+        .buildings()
+        .map(|(coord, building_type)| {
+            let state = grid.get(coord).unwrap();
+            building_yield(coord, building_type, state, power_grid, &powered, data)
+        })
+        .collect();
+    (yields, grids)
+}
+
+// One building's yield, given its tile and which tiles its power grid reaches.
+fn building_yield(
+    coord: GridCoord,
+    building_type: &BuildingType,
+    state: &TileState,
+    power_grid: bool,
+    powered: &HashSet<GridCoord>,
+    data: &dyn Fn(&BuildingType) -> Building,
+) -> BuildingYield {
+    let b = data(building_type);
+    let mut y = BuildingYield {
+        coord,
+        building_type: building_type.clone(),
+        production_type: b.resource_type.clone(),
+        production: b.resource_amount,
+        upkeep_type: b.upkeep_type.clone(),
+        upkeep: b.upkeep_amount,
+        active: true,
+        modifiers: Vec::new(),
+    };
+
+    // Only buildings connected to the Base produce anything or cost upkeep.
+    if !state.connected {
+        y.active = false;
+        y.modifiers.push(YieldModifier::NotConnected);
+        return y;
+    }
+    // With power grids, energy never reaches the global pool directly and
+    // consumers that their grid can't power stand idle.
+    if power_grid && b.upkeep_type == ResourceType::Energy {
+        if powered.contains(&coord) {
+            y.upkeep = 0;
+            y.modifiers.push(YieldModifier::PoweredByGrid);
+        } else {
+            y.active = false;
+            y.modifiers.push(YieldModifier::NotEnoughPower);
+            return y;
+        }
+    }
+    if power_grid && b.resource_type == ResourceType::Energy {
+        y.production = 0;
+        y.modifiers.push(YieldModifier::FeedsGrid);
+    }
+    y
+}
+
+// The income these yields add up to. Whatever whole units each grid has spare are stored.
+pub fn total(yields: &[BuildingYield], grids: &[PowerGrid]) -> ResourceAmounts {
+    let mut income = ResourceAmounts::default();
+    for (resource_type, amount) in yields.iter().flat_map(|y| y.amounts()) {
+        income.add(&resource_type, amount);
+    }
+    for g in grids.iter() {
+        income.add(&ResourceType::Energy, g.spare.floor() as i32);
+    }
+    income
+}
+
+// How `current` income would change if `building_type` went on `coord`.
+pub fn placement_delta(
+    grid: &MapGrid,
+    layout: GridLayout,
+    power_grid: bool,
+    coord: GridCoord,
+    building_type: &BuildingType,
+    current: ResourceAmounts,
+    data: &dyn Fn(&BuildingType) -> Building,
+) -> ResourceAmounts {
+    // A new building can merge or starve power grids anywhere along them, so those
+    // are simulated again in full.
+    if power_grid {
+        let mut grid = grid.clone();
+        grid.get_mut(coord).unwrap().building = Some(building_type.clone());
+        grid.update_connectivity(layout);
+        let (yields, grids) = compute(&grid, layout, power_grid, data);
+        return total(&yields, &grids) - current;
+    }
+    // Otherwise only the new building and whatever it connects to the Base change,
+    // and those made nothing before.
+    let yields: Vec<BuildingYield> = grid
+        .connected_by(coord, layout)
+        .into_iter()
+        .map(|c| {
+            let mut state = grid.get(c).unwrap().clone();
+            if c == coord {
+                state.building = Some(building_type.clone());
+            }
+            state.connected = true;
+            let placed = state.building.clone().unwrap();
+            building_yield(c, &placed, &state, false, &HashSet::new(), data)
+        })
+        .collect();
+    total(&yields, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(building_type: &BuildingType) -> Building {
+        match building_type {
+            BuildingType::Base => Building::base(),
+            BuildingType::Mine => Building::mine(),
+            BuildingType::Greenhouse => Building::greenhouse(),
+            BuildingType::SolarPanel => Building::solar_panel(),
+            BuildingType::Conduit => Building::conduit(),
+        }
+    }
+
+    // A row of tiles with the Base on the first one and `buildings` after it.
+    fn row(buildings: &[Option<BuildingType>]) -> MapGrid {
+        let mut grid = MapGrid::new(buildings.len() as i32 + 1, 1);
+        let base = grid.get_mut(GridCoord::new(0, 0)).unwrap();
+        base.start_tile = true;
+        base.building = Some(BuildingType::Base);
+        for (x, building) in buildings.iter().enumerate() {
+            grid.get_mut(GridCoord::new(x as i32 + 1, 0))
+                .unwrap()
+                .building = building.clone();
+        }
+        grid.update_connectivity(GridLayout::Square);
+        grid
+    }
+
+    fn yield_of(yields: &[BuildingYield], x: i32) -> &BuildingYield {
+        yields
+            .iter()
+            .find(|y| y.coord == GridCoord::new(x, 0))
+            .unwrap()
+    }
+
+    fn income(amounts: &ResourceAmounts) -> Vec<i32> {
+        ResourceType::STORED
+            .iter()
+            .map(|t| amounts.get(t))
+            .collect()
+    }
+
+    #[test]
+    fn unconnected_buildings_are_inactive() {
+        let grid = row(&[
+            Some(BuildingType::Greenhouse),
+            None,
+            Some(BuildingType::Greenhouse),
+        ]);
+        let (yields, grids) = compute(&grid, GridLayout::Square, false, &data);
+        assert!(grids.is_empty());
+
+        assert!(yield_of(&yields, 1).active);
+        assert!(yield_of(&yields, 1).modifiers.is_empty());
+        assert!(!yield_of(&yields, 3).active);
+        assert_eq!(
+            yield_of(&yields, 3).modifiers,
+            vec![YieldModifier::NotConnected]
+        );
+        // Food, metal, oxygen, energy.
+        assert_eq!(income(&total(&yields, &grids)), vec![1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn energy_goes_to_the_pool_without_power_grids() {
+        let grid = row(&[Some(BuildingType::SolarPanel), Some(BuildingType::Mine)]);
+        let (yields, grids) = compute(&grid, GridLayout::Square, false, &data);
+        assert_eq!(income(&total(&yields, &grids)), vec![0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn power_grids_power_their_consumers() {
+        let grid = row(&[
+            Some(BuildingType::SolarPanel),
+            Some(BuildingType::Mine),
+            Some(BuildingType::Mine),
+        ]);
+        let (yields, grids) = compute(&grid, GridLayout::Square, true, &data);
+        assert_eq!(grids.len(), 1);
+
+        assert_eq!(yield_of(&yields, 1).production, 0);
+        assert_eq!(
+            yield_of(&yields, 1).modifiers,
+            vec![YieldModifier::FeedsGrid]
+        );
+        assert!(yield_of(&yields, 2).active);
+        assert_eq!(yield_of(&yields, 2).upkeep, 0);
+        assert_eq!(
+            yield_of(&yields, 2).modifiers,
+            vec![YieldModifier::PoweredByGrid]
+        );
+        assert!(!yield_of(&yields, 3).active);
+        assert_eq!(
+            yield_of(&yields, 3).modifiers,
+            vec![YieldModifier::NotEnoughPower]
+        );
+        assert_eq!(income(&total(&yields, &grids)), vec![0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn placement_delta_counts_the_buildings_it_connects() {
+        let grid = row(&[
+            None,
+            Some(BuildingType::Greenhouse),
+            Some(BuildingType::Mine),
+        ]);
+        let (yields, grids) = compute(&grid, GridLayout::Square, false, &data);
+        let current = total(&yields, &grids);
+
+        let coord = GridCoord::new(1, 0);
+        let delta = placement_delta(
+            &grid,
+            GridLayout::Square,
+            false,
+            coord,
+            &BuildingType::Conduit,
+            current,
+            &data,
+        );
+        assert_eq!(income(&delta), vec![1, 1, 0, -1]);
+
+        // The same as placing it for real and adding everything up again.
+        let mut placed = grid.clone();
+        placed.get_mut(coord).unwrap().building = Some(BuildingType::Conduit);
+        placed.update_connectivity(GridLayout::Square);
+        let (yields, grids) = compute(&placed, GridLayout::Square, false, &data);
+        assert!(total(&yields, &grids) - current == delta);
+    }
+
+    #[test]
+    fn placement_delta_is_empty_when_nothing_connects() {
+        let mut grid = row(&[None, None]);
+        grid.get_mut(GridCoord::new(0, 0)).unwrap().building = None;
+        grid.update_connectivity(GridLayout::Square);
+        let delta = placement_delta(
+            &grid,
+            GridLayout::Square,
+            false,
+            GridCoord::new(2, 0),
+            &BuildingType::Greenhouse,
+            ResourceAmounts::default(),
+            &data,
+        );
+        assert_eq!(income(&delta), vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn placement_delta_simulates_power_grids_again() {
+        let grid = row(&[None, Some(BuildingType::Mine)]);
+        let (yields, grids) = compute(&grid, GridLayout::Square, true, &data);
+        let current = total(&yields, &grids);
+        assert_eq!(income(&current), vec![0, 0, 0, 0]);
+
+        let delta = placement_delta(
+            &grid,
+            GridLayout::Square,
+            true,
+            GridCoord::new(1, 0),
+            &BuildingType::SolarPanel,
+            current,
+            &data,
+        );
+        // The panel powers the mine and has nothing to spare.
+        assert_eq!(income(&delta), vec![0, 1, 0, 0]);
+    }
+}